@@ -0,0 +1,329 @@
+use crate::auth::{self, constant_time_eq};
+use crate::cache_affinity::CacheAffinity;
+use crate::metrics::ErrorRate;
+use crate::router::{ProviderStatus, Router};
+use crate::usage::UsageSummary;
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Query, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router as AxumRouter,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Admin API settings (`admin` in provider.json, read at startup)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminConfig {
+    /// Listen address; keep it on loopback unless the token is shared deliberately
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    /// Bearer token for `/admin`; generated into `~/.cc-proxy/admin_token` when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            bind: default_bind(),
+            token: None,
+        }
+    }
+}
+
+fn default_bind() -> String {
+    "127.0.0.1:18101".to_string()
+}
+
+#[derive(Clone)]
+struct AdminState {
+    router: Arc<Router>,
+    token: Arc<String>,
+    listen: Arc<String>,
+    started_at: Instant,
+}
+
+/// Everything `cc-proxy status` shows, served by `GET /admin/status`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    #[serde(rename = "uptimeSecs")]
+    pub uptime_secs: u64,
+    pub listen: String,
+    pub providers: Vec<ProviderStatus>,
+    pub affinity: Vec<AffinityPin>,
+    /// Attempts and failures per provider over the last 5 minutes
+    #[serde(rename = "errorRates")]
+    pub error_rates: Vec<ErrorRate>,
+    pub usage: UsageSummary,
+}
+
+/// An active affinity key and the provider it is pinned to
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AffinityPin {
+    pub key: String,
+    pub provider: String,
+    #[serde(rename = "expiresInSecs")]
+    pub expires_in_secs: u64,
+    pub requests: u32,
+}
+
+#[derive(Serialize)]
+struct AffinityEntry {
+    key: String,
+    #[serde(flatten)]
+    affinity: CacheAffinity,
+}
+
+#[derive(Deserialize)]
+struct ProviderSelector {
+    /// Provider id or provider `name`
+    provider: String,
+}
+
+#[derive(Deserialize)]
+struct AffinityQuery {
+    key: Option<String>,
+}
+
+pub fn admin_token_path() -> Result<PathBuf> {
+    let home = std::env::var("HOME").context("HOME environment variable not set")?;
+    Ok(PathBuf::from(home).join(".cc-proxy").join("admin_token"))
+}
+
+/// The configured token, or the one saved in `admin_token` (created on first use)
+pub fn resolve_token(config: &AdminConfig) -> Result<String> {
+    if let Some(token) = config.token.as_ref().filter(|t| !t.is_empty()) {
+        return Ok(token.clone());
+    }
+
+    let path = admin_token_path()?;
+    if let Ok(token) = fs::read_to_string(&path) {
+        let token = token.trim();
+        if !token.is_empty() {
+            return Ok(token.to_string());
+        }
+    }
+
+    let token = format!("cca-{}", hex::encode(rand::random::<[u8; 24]>()));
+    auth::save_token(&path, &token)?;
+    Ok(token)
+}
+
+pub fn create_admin_app(router: Arc<Router>, token: String, listen: &str) -> AxumRouter {
+    let state = AdminState {
+        router,
+        token: Arc::new(token),
+        listen: Arc::new(listen.to_string()),
+        started_at: Instant::now(),
+    };
+
+    AxumRouter::new()
+        .route("/admin/status", get(status))
+        .route("/admin/providers", get(list_providers))
+        .route("/admin/providers/disable", post(disable_provider))
+        .route("/admin/providers/enable", post(enable_provider))
+        .route("/admin/affinity", get(list_affinity).delete(clear_affinity))
+        .route("/admin/reload", post(reload))
+        .layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state)
+}
+
+/// Serve the admin API in the background; `listen` is the proxy's own address
+pub async fn spawn_admin_server(
+    router: Arc<Router>,
+    config: &AdminConfig,
+    listen: &str,
+) -> Result<()> {
+    let token = resolve_token(config)?;
+    let listener = tokio::net::TcpListener::bind(&config.bind)
+        .await
+        .with_context(|| format!("Failed to bind admin API to {}", config.bind))?;
+
+    let app = create_admin_app(router, token, listen);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("Admin API error: {}", e);
+        }
+    });
+
+    tracing::info!("🔧 Admin API listening on {}", config.bind);
+    Ok(())
+}
+
+async fn require_token(State(state): State<AdminState>, request: Request, next: Next) -> Response {
+    if token_matches(request.headers(), &state.token) {
+        next.run(request).await
+    } else {
+        error(StatusCode::UNAUTHORIZED, "Missing or invalid admin token")
+    }
+}
+
+fn token_matches(headers: &HeaderMap, expected: &str) -> bool {
+    let provided = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-admin-token").and_then(|v| v.to_str().ok()));
+
+    provided.is_some_and(|provided| constant_time_eq(provided, expected))
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+/// Query a running daemon (used by `cc-proxy status`)
+pub async fn fetch_status(config: &AdminConfig) -> Result<DaemonStatus> {
+    let token = resolve_token(config)?;
+    let response = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()?
+        .get(format!("http://{}/admin/status", config.bind))
+        .bearer_auth(token)
+        .send()
+        .await
+        .with_context(|| format!("Admin API not reachable at {}", config.bind))?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("Admin API returned {}", response.status());
+    }
+    response
+        .json()
+        .await
+        .context("Failed to parse admin status")
+}
+
+async fn status(State(state): State<AdminState>) -> Json<DaemonStatus> {
+    let providers = state.router.provider_statuses().await;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default();
+
+    let affinity = state
+        .router
+        .affinity_manager()
+        .entries()
+        .await
+        .into_iter()
+        .map(|(key, affinity)| AffinityPin {
+            key,
+            provider: providers
+                .iter()
+                .find(|p| p.id == affinity.provider_id)
+                .map_or(affinity.provider_id.clone(), |p| p.label.clone()),
+            expires_in_secs: (affinity.expire_at - now).max(0.0) as u64,
+            requests: affinity.request_count,
+        })
+        .collect();
+
+    // The rollups are enough for status; the raw records stay in `GET /usage`
+    let mut usage = UsageSummary::new(state.router.usage_snapshot());
+    usage.records.clear();
+    usage.recent_requests = state.router.recent_requests();
+
+    Json(DaemonStatus {
+        pid: std::process::id(),
+        uptime_secs: state.started_at.elapsed().as_secs(),
+        listen: state.listen.to_string(),
+        providers,
+        affinity,
+        error_rates: state.router.error_rates(),
+        usage,
+    })
+}
+
+async fn list_providers(State(state): State<AdminState>) -> Json<Vec<ProviderStatus>> {
+    Json(state.router.provider_statuses().await)
+}
+
+async fn disable_provider(
+    State(state): State<AdminState>,
+    Json(selector): Json<ProviderSelector>,
+) -> Response {
+    set_enabled(&state, &selector.provider, false).await
+}
+
+async fn enable_provider(
+    State(state): State<AdminState>,
+    Json(selector): Json<ProviderSelector>,
+) -> Response {
+    set_enabled(&state, &selector.provider, true).await
+}
+
+async fn set_enabled(state: &AdminState, selector: &str, enabled: bool) -> Response {
+    let ids = state.router.set_provider_enabled(selector, enabled).await;
+    if ids.is_empty() {
+        return error(
+            StatusCode::NOT_FOUND,
+            &format!("No provider matches {}", selector),
+        );
+    }
+    Json(json!({ "enabled": enabled, "providers": ids })).into_response()
+}
+
+async fn list_affinity(State(state): State<AdminState>) -> Json<Vec<AffinityEntry>> {
+    let entries = state.router.affinity_manager().entries().await;
+    Json(
+        entries
+            .into_iter()
+            .map(|(key, affinity)| AffinityEntry { key, affinity })
+            .collect(),
+    )
+}
+
+async fn clear_affinity(
+    State(state): State<AdminState>,
+    Query(query): Query<AffinityQuery>,
+) -> Json<Value> {
+    let manager = state.router.affinity_manager();
+    let removed = match query.key {
+        Some(key) => usize::from(manager.invalidate(&key).await),
+        None => manager.clear().await,
+    };
+    Json(json!({ "removed": removed }))
+}
+
+async fn reload(State(state): State<AdminState>) -> Response {
+    match state.router.reload_providers().await {
+        Ok(()) => Json(json!({ "reloaded": true })).into_response(),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_bearer_or_admin_header() {
+        let mut headers = HeaderMap::new();
+        assert!(!token_matches(&headers, "secret"));
+
+        headers.insert("authorization", "Bearer secret".parse().unwrap());
+        assert!(token_matches(&headers, "secret"));
+        assert!(!token_matches(&headers, "secret2"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "secret".parse().unwrap());
+        assert!(token_matches(&headers, "secret"));
+    }
+
+    #[test]
+    fn configured_token_wins() {
+        let config = AdminConfig {
+            token: Some("from-config".into()),
+            ..Default::default()
+        };
+        assert_eq!(resolve_token(&config).unwrap(), "from-config");
+    }
+}
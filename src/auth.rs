@@ -0,0 +1,145 @@
+use crate::config::load_proxy_config;
+use crate::quota::ClientLimits;
+use anyhow::{Context, Result};
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the client issued to the CLIs configured by `cc-proxy start`
+pub const LOCAL_CLIENT: &str = "local";
+
+/// A client allowed to use the proxy (`clients` in provider.json)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientToken {
+    /// Shown in usage accounting, metrics and logs
+    pub name: String,
+    pub token: String,
+    #[serde(flatten)]
+    pub limits: ClientLimits,
+}
+
+/// The token a request presents, from `x-api-key` or `Authorization: Bearer`
+pub fn presented_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| {
+            headers
+                .get("authorization")
+                .and_then(|v| v.to_str().ok())
+                .map(|auth| auth.strip_prefix("Bearer ").unwrap_or(auth))
+        })
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+/// The configured client whose token the request presents
+pub fn authenticate<'a>(
+    clients: &'a [ClientToken],
+    headers: &HeaderMap,
+) -> Option<&'a ClientToken> {
+    let presented = presented_token(headers)?;
+    clients
+        .iter()
+        .find(|client| constant_time_eq(presented, &client.token))
+}
+
+/// Compare in constant time so tokens can't be guessed byte by byte
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+pub fn local_token_path() -> Result<PathBuf> {
+    let home = std::env::var("HOME").context("HOME environment variable not set")?;
+    Ok(PathBuf::from(home).join(".cc-proxy").join("local_token"))
+}
+
+/// The `local` client saved in `local_token`, if `cc-proxy start` issued one
+pub fn saved_local_client() -> Option<ClientToken> {
+    let token = fs::read_to_string(local_token_path().ok()?).ok()?;
+    let token = token.trim();
+    (!token.is_empty()).then(|| ClientToken {
+        name: LOCAL_CLIENT.to_string(),
+        token: token.to_string(),
+        limits: ClientLimits::default(),
+    })
+}
+
+/// Token of the `local` client: the one listed in provider.json's `clients`,
+/// otherwise the one saved in `local_token` (created on first use)
+pub fn issue_local_token() -> Result<String> {
+    let configured = load_proxy_config()?
+        .clients
+        .into_iter()
+        .find(|client| client.name == LOCAL_CLIENT);
+    if let Some(client) = configured {
+        return Ok(client.token);
+    }
+
+    let token = format!("ccp-{}", hex::encode(rand::random::<[u8; 24]>()));
+    save_token(&local_token_path()?, &token)?;
+    Ok(token)
+}
+
+/// Write a generated token readable only by the current user
+pub fn save_token(path: &Path, token: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, token).with_context(|| format!("Failed to write token: {:?}", path))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clients() -> Vec<ClientToken> {
+        vec![
+            ClientToken {
+                name: "laptop".into(),
+                token: "ccp-laptop".into(),
+                limits: ClientLimits::default(),
+            },
+            ClientToken {
+                name: "ci".into(),
+                token: "ccp-ci".into(),
+                limits: ClientLimits::default(),
+            },
+        ]
+    }
+
+    #[test]
+    fn accepts_bearer_or_api_key() {
+        let clients = clients();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer ccp-ci".parse().unwrap());
+        assert_eq!(authenticate(&clients, &headers).unwrap().name, "ci");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "ccp-laptop".parse().unwrap());
+        assert_eq!(authenticate(&clients, &headers).unwrap().name, "laptop");
+    }
+
+    #[test]
+    fn rejects_unknown_or_missing_tokens() {
+        let clients = clients();
+        assert!(authenticate(&clients, &HeaderMap::new()).is_none());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer cc-proxy".parse().unwrap());
+        assert!(authenticate(&clients, &headers).is_none());
+        assert!(authenticate(&[], &headers).is_none());
+    }
+}
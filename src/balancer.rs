@@ -0,0 +1,157 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Selection strategy for endpoints within the same priority tier
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Keep the order from provider.json
+    #[default]
+    Ordered,
+    /// Rotate the starting endpoint on every request
+    RoundRobin,
+    /// Shuffle endpoints uniformly
+    Random,
+    /// Shuffle endpoints with probability proportional to `weight`
+    Weighted,
+}
+
+/// Orders endpoints inside a tier according to the configured strategy
+#[derive(Default)]
+pub struct Balancer {
+    // Round-robin cursors keyed by tier (e.g. "claude:0")
+    cursors: Mutex<HashMap<String, usize>>,
+}
+
+impl Balancer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the try order for a tier as indices into `weights`
+    pub fn order(&self, tier_key: &str, weights: &[u32], strategy: Strategy) -> Vec<usize> {
+        let len = weights.len();
+        let mut order: Vec<usize> = (0..len).collect();
+        if len <= 1 {
+            return order;
+        }
+
+        match strategy {
+            Strategy::Ordered => {}
+            Strategy::RoundRobin => {
+                let mut cursors = self.cursors.lock().unwrap();
+                let cursor = cursors.entry(tier_key.to_string()).or_insert(0);
+                order.rotate_left(*cursor % len);
+                *cursor = cursor.wrapping_add(1);
+            }
+            Strategy::Random => {
+                order.shuffle(&mut rand::thread_rng());
+            }
+            Strategy::Weighted => {
+                // Weighted sampling without replacement (Efraimidis-Spirakis):
+                // sort by u^(1/w) descending, zero-weight endpoints go last.
+                let mut rng = rand::thread_rng();
+                let mut keyed: Vec<(f64, usize)> = order
+                    .into_iter()
+                    .map(|idx| {
+                        let weight = weights[idx];
+                        let key = if weight == 0 {
+                            -1.0
+                        } else {
+                            rng.gen::<f64>().powf(1.0 / weight as f64)
+                        };
+                        (key, idx)
+                    })
+                    .collect();
+                keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+                order = keyed.into_iter().map(|(_, idx)| idx).collect();
+            }
+        }
+
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordered_keeps_file_order() {
+        let balancer = Balancer::new();
+        assert_eq!(
+            balancer.order("claude:0", &[1, 1, 1], Strategy::Ordered),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn round_robin_rotates_per_tier() {
+        let balancer = Balancer::new();
+        let weights = [1, 1, 1];
+
+        assert_eq!(
+            balancer.order("claude:0", &weights, Strategy::RoundRobin),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            balancer.order("claude:0", &weights, Strategy::RoundRobin),
+            vec![1, 2, 0]
+        );
+        // Other tiers keep their own cursor
+        assert_eq!(
+            balancer.order("claude:1", &weights, Strategy::RoundRobin),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            balancer.order("claude:0", &weights, Strategy::RoundRobin),
+            vec![2, 0, 1]
+        );
+    }
+
+    #[test]
+    fn random_and_weighted_return_permutations() {
+        let balancer = Balancer::new();
+        for strategy in [Strategy::Random, Strategy::Weighted] {
+            let mut order = balancer.order("codex:0", &[3, 1, 2, 5], strategy);
+            order.sort_unstable();
+            assert_eq!(order, vec![0, 1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn weighted_prefers_heavier_endpoints() {
+        let balancer = Balancer::new();
+        let mut first_heavy = 0;
+        for _ in 0..1000 {
+            if balancer.order("claude:0", &[1, 9], Strategy::Weighted)[0] == 1 {
+                first_heavy += 1;
+            }
+        }
+        assert!(
+            first_heavy > 800,
+            "heavy endpoint first {} times",
+            first_heavy
+        );
+    }
+
+    #[test]
+    fn weighted_puts_zero_weight_last() {
+        let balancer = Balancer::new();
+        for _ in 0..100 {
+            assert_eq!(
+                balancer.order("claude:0", &[0, 1], Strategy::Weighted),
+                vec![1, 0]
+            );
+        }
+    }
+
+    #[test]
+    fn strategy_parses_kebab_case() {
+        let strategy: Strategy = serde_json::from_str("\"round-robin\"").unwrap();
+        assert_eq!(strategy, Strategy::RoundRobin);
+    }
+}
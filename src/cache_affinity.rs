@@ -1,38 +1,120 @@
+use crate::auth;
+use anyhow::{Context, Result};
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
 use tokio::time::{interval, Duration};
 
-#[derive(Debug, Clone)]
+/// How often the affinity store is snapshotted to disk
+const SNAPSHOT_INTERVAL_SECS: u64 = 30;
+
+/// Cache affinity settings (`affinity` in provider.json)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AffinityConfig {
+    /// Seconds a pin survives without traffic; every hit restarts the clock
+    #[serde(rename = "ttlSecs", default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+    /// Snapshot affinities to `~/.cc-proxy/affinity.json` so they survive
+    /// restarts (read at startup)
+    #[serde(default = "default_persist")]
+    pub persist: bool,
+}
+
+impl Default for AffinityConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: default_ttl_secs(),
+            persist: default_persist(),
+        }
+    }
+}
+
+/// Matches Anthropic's default 5-minute prompt cache lifetime
+fn default_ttl_secs() -> u64 {
+    300
+}
+
+fn default_persist() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CacheAffinity {
+    #[serde(rename = "providerId")]
     pub provider_id: String,
+    /// Unix timestamp, so entries stay meaningful across restarts
+    #[serde(rename = "expireAt")]
     pub expire_at: f64,
+    #[serde(rename = "requestCount")]
     pub request_count: u32,
+    /// Longer upstream cache lifetime seen in usage (e.g. 1h); 0 means the default TTL
+    #[serde(rename = "cacheTtlSecs", default)]
+    pub cache_ttl_secs: u64,
 }
 
 #[derive(Clone)]
 pub struct CacheAffinityManager {
     store: Arc<RwLock<HashMap<String, CacheAffinity>>>,
-    default_ttl: u64,
+    default_ttl: Arc<AtomicU64>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
 }
 
 impl CacheAffinityManager {
     pub fn new(default_ttl: u64) -> Self {
         Self {
             store: Arc::new(RwLock::new(HashMap::new())),
-            default_ttl,
+            default_ttl: Arc::new(AtomicU64::new(default_ttl)),
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
         }
     }
 
+    /// `(hits, misses)` of `get` since startup
+    pub fn lookup_counts(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Change the TTL applied from the next hit on (config hot reload)
+    pub fn set_default_ttl(&self, ttl: u64) {
+        self.default_ttl.store(ttl, Ordering::Relaxed);
+    }
+
+    fn ttl_for(&self, affinity: &CacheAffinity) -> u64 {
+        self.default_ttl
+            .load(Ordering::Relaxed)
+            .max(affinity.cache_ttl_secs)
+    }
+
     /// Generate cache affinity key
-    /// Format: {user_id}:{kind}:{model}
-    pub fn generate_key(user_id: &str, kind: &str, model: &str) -> String {
-        format!("{}:{}:{}", user_id, kind, model)
+    /// Format: {session_id}:{kind}:{model}
+    pub fn generate_key(session_id: &str, kind: &str, model: &str) -> String {
+        format!("{}:{}:{}", session_id, kind, model)
     }
 
     /// Get cached provider ID if affinity exists and is valid
     pub async fn get(&self, key: &str) -> Option<String> {
+        let found = self.lookup(key).await;
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    async fn lookup(&self, key: &str) -> Option<String> {
         // First, try with read lock (fast path for concurrent reads)
         {
             let store = self.store.read().await;
@@ -56,10 +138,11 @@ impl CacheAffinityManager {
                 // Drop read lock before acquiring write lock for counter update
                 drop(store);
 
-                // Update request count with write lock
+                // Update request count and slide the expiry with write lock
                 let mut store = self.store.write().await;
                 if let Some(affinity) = store.get_mut(key) {
                     affinity.request_count += 1;
+                    affinity.expire_at = now + self.ttl_for(affinity) as f64;
                     tracing::debug!(
                         "Cache affinity hit: {} → {} (count: {})",
                         key,
@@ -75,32 +158,152 @@ impl CacheAffinityManager {
         None
     }
 
-    /// Set cache affinity for a key
+    /// Set cache affinity for a key. Re-pinning the same provider keeps
+    /// its extended cache lifetime.
     pub async fn set(&self, key: &str, provider_id: &str) {
         let now = current_time();
-        let affinity = CacheAffinity {
-            provider_id: provider_id.to_string(),
-            expire_at: now + self.default_ttl as f64,
-            request_count: 1,
+        let mut store = self.store.write().await;
+
+        let affinity = match store.get(key) {
+            Some(existing) if existing.provider_id == provider_id => existing.clone(),
+            _ => CacheAffinity {
+                provider_id: provider_id.to_string(),
+                expire_at: now,
+                request_count: 1,
+                cache_ttl_secs: 0,
+            },
         };
+        let ttl = self.ttl_for(&affinity);
+        store.insert(
+            key.to_string(),
+            CacheAffinity {
+                expire_at: now + ttl as f64,
+                ..affinity
+            },
+        );
+
+        tracing::debug!(
+            "Cache affinity set: {} → {} (expires in {}s)",
+            key,
+            provider_id,
+            ttl
+        );
+    }
 
+    /// Keep a pin alive as long as the provider's cache: called when the
+    /// response shows cache writes with a longer lifetime (e.g. 1h)
+    pub async fn extend(&self, key: &str, provider_id: &str, cache_ttl_secs: u64) {
         let mut store = self.store.write().await;
-        store.insert(key.to_string(), affinity);
+        let Some(affinity) = store.get_mut(key) else {
+            return;
+        };
+        if affinity.provider_id != provider_id {
+            return;
+        }
 
+        affinity.cache_ttl_secs = affinity.cache_ttl_secs.max(cache_ttl_secs);
+        affinity.expire_at = current_time() + self.ttl_for(affinity) as f64;
         tracing::debug!(
-            "Cache affinity set: {} → {} (expires in {}s)",
+            "Cache affinity extended: {} → {} ({}s upstream cache)",
             key,
             provider_id,
-            self.default_ttl
+            affinity.cache_ttl_secs
         );
     }
 
     /// Invalidate cache affinity (called when cached provider fails)
-    pub async fn invalidate(&self, key: &str) {
+    pub async fn invalidate(&self, key: &str) -> bool {
         let mut store = self.store.write().await;
-        if store.remove(key).is_some() {
+        let removed = store.remove(key).is_some();
+        if removed {
             tracing::warn!("Cache affinity invalidated: {}", key);
         }
+        removed
+    }
+
+    /// All unexpired affinities, sorted by key
+    pub async fn entries(&self) -> Vec<(String, CacheAffinity)> {
+        let now = current_time();
+        let mut entries: Vec<(String, CacheAffinity)> = self
+            .store
+            .read()
+            .await
+            .iter()
+            .filter(|(_, affinity)| affinity.expire_at > now)
+            .map(|(key, affinity)| (key.clone(), affinity.clone()))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    /// Drop every affinity. Returns how many were removed.
+    pub async fn clear(&self) -> usize {
+        let mut store = self.store.write().await;
+        let removed = store.len();
+        store.clear();
+        tracing::info!("Cleared {} cache affinities", removed);
+        removed
+    }
+
+    /// Write all unexpired affinities to `path` (atomically, via a temp file)
+    pub async fn save_snapshot(&self, path: &Path) -> Result<usize> {
+        let now = current_time();
+        let snapshot: HashMap<String, CacheAffinity> = self
+            .store
+            .read()
+            .await
+            .iter()
+            .filter(|(_, affinity)| affinity.expire_at > now)
+            .map(|(key, affinity)| (key.clone(), affinity.clone()))
+            .collect();
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string(&snapshot)?)
+            .with_context(|| format!("Failed to write affinity snapshot: {:?}", tmp))?;
+        fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to write affinity snapshot: {:?}", path))?;
+
+        Ok(snapshot.len())
+    }
+
+    /// Restore unexpired affinities from a snapshot. Returns how many were loaded.
+    pub async fn load_snapshot(&self, path: &Path) -> Result<usize> {
+        if !path.exists() {
+            return Ok(0);
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read affinity snapshot: {:?}", path))?;
+        let snapshot: HashMap<String, CacheAffinity> = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse affinity snapshot: {:?}", path))?;
+
+        let now = current_time();
+        let mut store = self.store.write().await;
+        let before = store.len();
+        for (key, affinity) in snapshot {
+            if affinity.expire_at > now {
+                store.entry(key).or_insert(affinity);
+            }
+        }
+
+        Ok(store.len() - before)
+    }
+
+    /// Start background task that periodically snapshots the store to `path`
+    pub fn start_snapshot_task(manager: Arc<Self>, path: PathBuf) {
+        tokio::spawn(async move {
+            let mut ticker = interval(Duration::from_secs(SNAPSHOT_INTERVAL_SECS));
+
+            loop {
+                ticker.tick().await;
+
+                if let Err(e) = manager.save_snapshot(&path).await {
+                    tracing::debug!("Failed to snapshot cache affinity: {}", e);
+                }
+            }
+        });
     }
 
     /// Start background cleanup task
@@ -130,6 +333,71 @@ impl CacheAffinityManager {
     }
 }
 
+/// Identify the conversation a request belongs to, so each session gets its
+/// own affinity. Sources, most specific first:
+/// 1. Explicit session ids: Claude Code `metadata.user_id` (embeds the session)
+///    or session header; Codex `prompt_cache_key` or session/conversation header;
+///    Chat Completions `prompt_cache_key` or `user`
+/// 2. Fingerprint of the system prompt plus the first message
+/// 3. The client's API token
+pub fn session_id(kind: &str, request: &Value, headers: &HeaderMap) -> String {
+    let (body_fields, header_names): (&[&str], &[&str]) = match kind {
+        "claude" => (&["/metadata/user_id"], &["x-claude-code-session-id"]),
+        "openai" => (&["/prompt_cache_key", "/user"], &[]),
+        _ => (&["/prompt_cache_key"], &["session_id", "conversation_id"]),
+    };
+
+    let explicit = body_fields
+        .iter()
+        .find_map(|pointer| request.pointer(pointer).and_then(Value::as_str))
+        .or_else(|| {
+            header_names
+                .iter()
+                .find_map(|name| headers.get(*name).and_then(|v| v.to_str().ok()))
+        })
+        .filter(|id| !id.is_empty());
+    if let Some(id) = explicit {
+        return format!("session-{}", hash_string(id));
+    }
+
+    // Messages API: `system` + `messages`; Responses API: `instructions` + `input`;
+    // Chat Completions: the leading system messages + the first other one
+    let fingerprint = match kind {
+        "claude" => request["messages"]
+            .get(0)
+            .map(|first| format!("{}\n{}", request["system"], first)),
+        "openai" => request["messages"].as_array().and_then(|messages| {
+            let first = messages.iter().position(|message| {
+                !matches!(message["role"].as_str(), Some("system" | "developer"))
+            })?;
+            let prefix: Vec<String> = messages[..=first].iter().map(Value::to_string).collect();
+            Some(prefix.join("\n"))
+        }),
+        _ => request["input"]
+            .get(0)
+            .map(|first| format!("{}\n{}", request["instructions"], first)),
+    };
+    if let Some(fingerprint) = fingerprint {
+        return format!("prompt-{}", hash_string(&fingerprint));
+    }
+
+    client_id(headers)
+}
+
+/// Identify the calling client by a hash of its token (`x-api-key` or bearer)
+pub fn client_id(headers: &HeaderMap) -> String {
+    match auth::presented_token(headers) {
+        Some(token) => format!("client-{}", hash_string(token)),
+        None => "anonymous".to_string(),
+    }
+}
+
+/// Location of the persisted affinity snapshot
+pub fn affinity_file_path() -> Result<PathBuf> {
+    let home = std::env::var("HOME").context("HOME environment variable not set")?;
+    Ok(PathBuf::from(home).join(".cc-proxy").join("affinity.json"))
+}
+
 /// Get current Unix timestamp in seconds
 fn current_time() -> f64 {
     SystemTime::now()
@@ -170,6 +438,7 @@ mod tests {
 
         let store = manager.store.read().await;
         assert_eq!(store.get(key).unwrap().request_count, 3);
+        assert_eq!(manager.lookup_counts(), (2, 1));
     }
 
     #[tokio::test]
@@ -187,6 +456,36 @@ mod tests {
         assert!(manager.get(key).await.is_none());
     }
 
+    #[tokio::test]
+    async fn test_cache_affinity_slides_on_hit() {
+        let manager = CacheAffinityManager::new(2);
+        let key = "session:claude:claude-sonnet-4";
+
+        manager.set(key, "provider1").await;
+        tokio::time::sleep(Duration::from_millis(1200)).await;
+        assert!(manager.get(key).await.is_some());
+
+        // Past the original expiry, but the hit restarted the clock
+        tokio::time::sleep(Duration::from_millis(1200)).await;
+        assert!(manager.get(key).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_cache_affinity_extends_for_long_cache() {
+        let manager = CacheAffinityManager::new(300);
+        let key = "session:claude:claude-sonnet-4";
+
+        manager.set(key, "provider1").await;
+        manager.extend(key, "provider2", 3600).await; // not the pinned provider
+        manager.extend(key, "provider1", 3600).await;
+        manager.set(key, "provider1").await;
+
+        let store = manager.store.read().await;
+        let affinity = store.get(key).unwrap();
+        assert_eq!(affinity.cache_ttl_secs, 3600);
+        assert!(affinity.expire_at > current_time() + 3000.0);
+    }
+
     #[tokio::test]
     async fn test_cache_affinity_invalidate() {
         let manager = CacheAffinityManager::new(300);
@@ -199,6 +498,132 @@ mod tests {
         assert!(manager.get(key).await.is_none());
     }
 
+    #[tokio::test]
+    async fn test_snapshot_round_trip_skips_expired() {
+        let path = std::env::temp_dir().join(format!(
+            "cc-proxy-affinity-{}-{}.json",
+            std::process::id(),
+            current_time()
+        ));
+
+        let manager = CacheAffinityManager::new(300);
+        manager.set("live", "provider1").await;
+        manager.store.write().await.insert(
+            "stale".into(),
+            CacheAffinity {
+                provider_id: "provider2".into(),
+                expire_at: current_time() - 1.0,
+                request_count: 1,
+                cache_ttl_secs: 0,
+            },
+        );
+        assert_eq!(manager.save_snapshot(&path).await.unwrap(), 1);
+
+        let restored = CacheAffinityManager::new(300);
+        assert_eq!(restored.load_snapshot(&path).await.unwrap(), 1);
+        assert_eq!(restored.get("live").await, Some("provider1".to_string()));
+        assert!(restored.get("stale").await.is_none());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_session_id_prefers_explicit_ids() {
+        let headers = HeaderMap::new();
+        let first = serde_json::json!({
+            "metadata": { "user_id": "user_abc_account__session_1111" },
+            "messages": [{ "role": "user", "content": "hi" }]
+        });
+        let second = serde_json::json!({
+            "metadata": { "user_id": "user_abc_account__session_2222" },
+            "messages": [{ "role": "user", "content": "hi" }]
+        });
+
+        let id = session_id("claude", &first, &headers);
+        assert!(id.starts_with("session-"));
+        assert_ne!(id, session_id("claude", &second, &headers));
+
+        let codex = serde_json::json!({ "prompt_cache_key": "conv-1", "input": [] });
+        assert!(session_id("codex", &codex, &headers).starts_with("session-"));
+    }
+
+    #[test]
+    fn test_session_id_fingerprint_is_stable_across_turns() {
+        let headers = HeaderMap::new();
+        let turn1 = serde_json::json!({
+            "system": "You are helpful",
+            "messages": [{ "role": "user", "content": "fix the bug" }]
+        });
+        let turn2 = serde_json::json!({
+            "system": "You are helpful",
+            "messages": [
+                { "role": "user", "content": "fix the bug" },
+                { "role": "assistant", "content": "done" },
+                { "role": "user", "content": "thanks" }
+            ]
+        });
+        let other = serde_json::json!({
+            "system": "You are helpful",
+            "messages": [{ "role": "user", "content": "write docs" }]
+        });
+
+        let id = session_id("claude", &turn1, &headers);
+        assert!(id.starts_with("prompt-"));
+        assert_eq!(id, session_id("claude", &turn2, &headers));
+        assert_ne!(id, session_id("claude", &other, &headers));
+    }
+
+    #[test]
+    fn test_session_id_chat_fingerprint_includes_system_messages() {
+        let headers = HeaderMap::new();
+        let turn1 = serde_json::json!({
+            "messages": [
+                { "role": "system", "content": "You are helpful" },
+                { "role": "user", "content": "fix the bug" }
+            ]
+        });
+        let turn2 = serde_json::json!({
+            "messages": [
+                { "role": "system", "content": "You are helpful" },
+                { "role": "user", "content": "fix the bug" },
+                { "role": "assistant", "content": "done" },
+                { "role": "user", "content": "thanks" }
+            ]
+        });
+        let other = serde_json::json!({
+            "messages": [
+                { "role": "system", "content": "You are helpful" },
+                { "role": "user", "content": "write docs" }
+            ]
+        });
+
+        let id = session_id("openai", &turn1, &headers);
+        assert!(id.starts_with("prompt-"));
+        assert_eq!(id, session_id("openai", &turn2, &headers));
+        assert_ne!(id, session_id("openai", &other, &headers));
+
+        let keyed = serde_json::json!({ "user": "alice", "input": "embed me" });
+        assert!(session_id("openai", &keyed, &headers).starts_with("session-"));
+    }
+
+    #[test]
+    fn test_session_id_falls_back_to_client_token() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer cc-proxy".parse().unwrap());
+
+        let id = session_id("claude", &serde_json::json!({}), &headers);
+        assert_eq!(id, format!("client-{}", hash_string("cc-proxy")));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "ccp-laptop".parse().unwrap());
+        let id = session_id("claude", &serde_json::json!({}), &headers);
+        assert_eq!(id, format!("client-{}", hash_string("ccp-laptop")));
+        assert_eq!(
+            session_id("claude", &serde_json::json!({}), &HeaderMap::new()),
+            "anonymous"
+        );
+    }
+
     #[test]
     fn test_hash_string() {
         let hash1 = hash_string("sk-ant-api-key-123");
@@ -0,0 +1,292 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Circuit breaker settings (`circuitBreaker` in provider.json)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures before a provider is skipped (0 disables the breaker)
+    #[serde(rename = "failureThreshold", default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// How long an open provider is skipped before a probe is let through
+    #[serde(rename = "cooldownSecs", default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_failure_threshold(),
+            cooldown_secs: default_cooldown_secs(),
+        }
+    }
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+fn default_cooldown_secs() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct BreakerEntry {
+    state: BreakerState,
+    consecutive_failures: u32,
+    /// When the breaker opened, or when the current half-open probe started
+    opened_at: Option<Instant>,
+}
+
+impl Default for BreakerEntry {
+    fn default() -> Self {
+        Self {
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            opened_at: None,
+        }
+    }
+}
+
+/// Point-in-time view of one provider's breaker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BreakerSnapshot {
+    pub provider: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    pub retry_in_secs: Option<u64>,
+}
+
+/// Per-provider health state machine (closed → open → half-open → closed)
+#[derive(Default)]
+pub struct CircuitBreaker {
+    entries: Mutex<HashMap<String, BreakerEntry>>,
+}
+
+impl CircuitBreaker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check whether a request may be sent to the provider.
+    /// An open breaker whose cooldown has elapsed lets exactly one probe through.
+    /// A probe that never reports back (e.g. the client disconnected) is given
+    /// up after another cooldown, and a new one is let through.
+    pub fn try_acquire(&self, provider_id: &str, config: &CircuitBreakerConfig) -> bool {
+        if config.failure_threshold == 0 {
+            return true;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(provider_id) else {
+            return true;
+        };
+
+        if entry.state == BreakerState::Closed {
+            return true;
+        }
+
+        let cooled_down = entry
+            .opened_at
+            .map(|at| at.elapsed() >= Duration::from_secs(config.cooldown_secs))
+            .unwrap_or(true);
+        if cooled_down {
+            if entry.state == BreakerState::HalfOpen {
+                tracing::warn!("Circuit probe abandoned, probing again: {}", provider_id);
+            } else {
+                tracing::info!("Circuit half-open, probing: {}", provider_id);
+            }
+            entry.state = BreakerState::HalfOpen;
+            entry.opened_at = Some(Instant::now());
+        }
+        cooled_down
+    }
+
+    /// Record a successful request; closes the breaker
+    pub fn record_success(&self, provider_id: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.remove(provider_id) {
+            if entry.state != BreakerState::Closed {
+                tracing::info!("Circuit closed: {}", provider_id);
+            }
+        }
+    }
+
+    /// Record a failed request; opens the breaker after enough consecutive failures
+    pub fn record_failure(&self, provider_id: &str, config: &CircuitBreakerConfig) {
+        if config.failure_threshold == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(provider_id.to_string()).or_default();
+        entry.consecutive_failures += 1;
+
+        let should_open = entry.state == BreakerState::HalfOpen
+            || entry.consecutive_failures >= config.failure_threshold;
+        if should_open {
+            if entry.state != BreakerState::Open {
+                tracing::warn!(
+                    "Circuit open: {} after {} consecutive failures (cooldown {}s)",
+                    provider_id,
+                    entry.consecutive_failures,
+                    config.cooldown_secs
+                );
+            }
+            entry.state = BreakerState::Open;
+            entry.opened_at = Some(Instant::now());
+        }
+    }
+
+    /// Snapshot every provider that has failed since its last success
+    pub fn snapshot(&self, config: &CircuitBreakerConfig) -> Vec<BreakerSnapshot> {
+        let entries = self.entries.lock().unwrap();
+        let mut snapshot: Vec<BreakerSnapshot> = entries
+            .iter()
+            .map(|(provider_id, entry)| BreakerSnapshot {
+                provider: provider_id.clone(),
+                state: entry.state,
+                consecutive_failures: entry.consecutive_failures,
+                retry_in_secs: match (entry.state, entry.opened_at) {
+                    (BreakerState::Open, Some(at)) => {
+                        Some(config.cooldown_secs.saturating_sub(at.elapsed().as_secs()))
+                    }
+                    _ => None,
+                },
+            })
+            .collect();
+        snapshot.sort_by(|a, b| a.provider.cmp(&b.provider));
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl CircuitBreaker {
+        fn state(&self, provider_id: &str) -> BreakerState {
+            let entries = self.entries.lock().unwrap();
+            entries
+                .get(provider_id)
+                .map(|entry| entry.state)
+                .unwrap_or(BreakerState::Closed)
+        }
+
+        /// Pretend the last state change happened `secs` ago
+        fn backdate(&self, provider_id: &str, secs: u64) {
+            let mut entries = self.entries.lock().unwrap();
+            let entry = entries.get_mut(provider_id).unwrap();
+            entry.opened_at = entry
+                .opened_at
+                .and_then(|at| at.checked_sub(Duration::from_secs(secs)));
+        }
+    }
+
+    fn config(cooldown_secs: u64) -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            failure_threshold: 2,
+            cooldown_secs,
+        }
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new();
+        let config = config(60);
+
+        breaker.record_failure("p1", &config);
+        assert!(breaker.try_acquire("p1", &config));
+
+        breaker.record_failure("p1", &config);
+        assert_eq!(breaker.state("p1"), BreakerState::Open);
+        assert!(!breaker.try_acquire("p1", &config));
+    }
+
+    #[test]
+    fn success_resets_failure_count() {
+        let breaker = CircuitBreaker::new();
+        let config = config(60);
+
+        breaker.record_failure("p1", &config);
+        breaker.record_success("p1");
+        breaker.record_failure("p1", &config);
+
+        assert_eq!(breaker.state("p1"), BreakerState::Closed);
+    }
+
+    #[test]
+    fn half_open_allows_single_probe() {
+        let breaker = CircuitBreaker::new();
+        let config = config(60);
+
+        breaker.record_failure("p1", &config);
+        breaker.record_failure("p1", &config);
+        breaker.backdate("p1", 60);
+
+        // Cooldown elapsed: one probe, then blocked
+        assert!(breaker.try_acquire("p1", &config));
+        assert_eq!(breaker.state("p1"), BreakerState::HalfOpen);
+        assert!(!breaker.try_acquire("p1", &config));
+
+        breaker.record_success("p1");
+        assert_eq!(breaker.state("p1"), BreakerState::Closed);
+        assert!(breaker.try_acquire("p1", &config));
+    }
+
+    #[test]
+    fn failed_probe_reopens() {
+        let breaker = CircuitBreaker::new();
+        let config = config(60);
+
+        breaker.record_failure("p1", &config);
+        breaker.record_failure("p1", &config);
+        breaker.backdate("p1", 60);
+        assert!(breaker.try_acquire("p1", &config));
+
+        breaker.record_failure("p1", &config);
+        assert_eq!(breaker.state("p1"), BreakerState::Open);
+        assert!(!breaker.try_acquire("p1", &config));
+    }
+
+    #[test]
+    fn abandoned_probe_is_retried_after_cooldown() {
+        let breaker = CircuitBreaker::new();
+        let config = config(60);
+
+        breaker.record_failure("p1", &config);
+        breaker.record_failure("p1", &config);
+        breaker.backdate("p1", 60);
+        assert!(breaker.try_acquire("p1", &config));
+
+        // The probe never reports back; after another cooldown a new one goes through
+        assert!(!breaker.try_acquire("p1", &config));
+        breaker.backdate("p1", 60);
+        assert!(breaker.try_acquire("p1", &config));
+        assert_eq!(breaker.state("p1"), BreakerState::HalfOpen);
+        assert!(!breaker.try_acquire("p1", &config));
+    }
+
+    #[test]
+    fn zero_threshold_disables_breaker() {
+        let breaker = CircuitBreaker::new();
+        let config = CircuitBreakerConfig {
+            failure_threshold: 0,
+            cooldown_secs: 60,
+        };
+
+        for _ in 0..10 {
+            breaker.record_failure("p1", &config);
+        }
+        assert!(breaker.try_acquire("p1", &config));
+    }
+}
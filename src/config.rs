@@ -0,0 +1,54 @@
+use crate::admin::AdminConfig;
+use crate::auth::{self, ClientToken};
+use crate::balancer::Strategy;
+use crate::cache_affinity::AffinityConfig;
+use crate::circuit_breaker::CircuitBreakerConfig;
+use crate::pricing::PriceRule;
+use crate::provider::get_config_path;
+use crate::timeouts::TimeoutConfig;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Proxy-wide settings stored alongside `providers` in provider.json
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    /// How endpoints sharing the same `level` are ordered
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(rename = "circuitBreaker", default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    #[serde(default)]
+    pub affinity: AffinityConfig,
+    /// Per-model (and optionally per-provider) token prices for cost estimates
+    #[serde(default)]
+    pub pricing: Vec<PriceRule>,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    /// Tokens accepted from clients; requests presenting any other token get 401
+    #[serde(default)]
+    pub clients: Vec<ClientToken>,
+    /// Global connect / first-byte / idle timeouts
+    #[serde(flatten)]
+    pub timeouts: TimeoutConfig,
+}
+
+/// Load proxy-wide settings from the provider config file. The `local`
+/// client issued by `cc-proxy start` is added unless provider.json lists one.
+pub fn load_proxy_config() -> Result<ProxyConfig> {
+    let config_path = get_config_path()?;
+
+    let mut config: ProxyConfig = if config_path.exists() {
+        let content = fs::read_to_string(&config_path)
+            .with_context(|| format!("Failed to read provider config: {:?}", config_path))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse proxy settings: {:?}", config_path))?
+    } else {
+        ProxyConfig::default()
+    };
+
+    if !config.clients.iter().any(|c| c.name == auth::LOCAL_CLIENT) {
+        config.clients.extend(auth::saved_local_client());
+    }
+    Ok(config)
+}
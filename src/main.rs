@@ -1,11 +1,30 @@
+mod admin;
+mod auth;
+mod balancer;
 mod cache_affinity;
+mod circuit_breaker;
+mod config;
+mod messages_chat;
+mod metrics;
+mod model_rules;
+mod pricing;
 mod provider;
+mod quota;
+mod rate_limits;
+mod responses_chat;
+mod responses_events;
+mod responses_messages;
 mod router;
 mod server;
 mod settings;
+mod sse;
+mod timeouts;
+mod upstream_error;
+mod usage;
 
 use anyhow::Result;
 use cache_affinity::CacheAffinityManager;
+use circuit_breaker::BreakerState;
 use local_ip_address::{list_afinet_netifas, local_ip};
 use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use router::Router;
@@ -18,7 +37,8 @@ use std::sync::Arc;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 const DEFAULT_BIND_ADDR: &str = "0.0.0.0:18100";
-const CACHE_TTL: u64 = 300; // 5 minutes
+/// Latest requests listed by `cc-proxy status`
+const STATUS_RECENT_REQUESTS: usize = 5;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -35,7 +55,7 @@ async fn main() -> Result<()> {
     match args.get(1).map(|s| s.as_str()) {
         Some("start") => start_daemon().await,
         Some("stop") => stop_daemon(),
-        Some("status") => show_status(),
+        Some("status") => show_status(args.iter().any(|a| a == "--json")).await,
         Some("help") | Some("--help") | Some("-h") => {
             print_help();
             Ok(())
@@ -65,9 +85,11 @@ async fn start_daemon() -> Result<()> {
 
     let advertise_addr = detect_advertise_addr(DEFAULT_BIND_ADDR);
 
-    // Configure CLI tools
+    // Configure CLI tools with the token issued to this machine
     println!("⚙️  Configuring CLI tools...");
-    if let Err(e) = settings::configure_all(&advertise_addr) {
+    if let Err(e) =
+        auth::issue_local_token().and_then(|token| settings::configure_all(&advertise_addr, &token))
+    {
         tracing::warn!("Failed to configure CLI tools: {}", e);
         println!("⚠️  Warning: Failed to configure CLI tools automatically");
         println!("   You may need to configure Claude Code and Codex manually");
@@ -75,43 +97,115 @@ async fn start_daemon() -> Result<()> {
     println!();
 
     // Initialize cache affinity manager
-    let affinity_manager = Arc::new(CacheAffinityManager::new(CACHE_TTL));
+    let proxy_config = config::load_proxy_config().unwrap_or_default();
+    let affinity_manager = Arc::new(CacheAffinityManager::new(proxy_config.affinity.ttl_secs));
 
     // Start cleanup task
     CacheAffinityManager::start_cleanup_task(affinity_manager.clone());
 
-    // Keep provider responses compressed so headers stay consistent end-to-end.
-    let http_client = reqwest::Client::builder()
-        .no_gzip()
-        .no_deflate()
-        .no_brotli()
-        .build()?;
+    // Restore affinities from the previous run and keep the snapshot fresh
+    let affinity_path = if proxy_config.affinity.persist {
+        cache_affinity::affinity_file_path().ok()
+    } else {
+        None
+    };
+    if let Some(path) = &affinity_path {
+        match affinity_manager.load_snapshot(path).await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("Restored {} cache affinities", count),
+            Err(e) => tracing::warn!("Failed to restore cache affinity: {}", e),
+        }
+        CacheAffinityManager::start_snapshot_task(affinity_manager.clone(), path.clone());
+    }
 
     // Initialize router
-    let router = Arc::new(Router::new(affinity_manager.clone(), http_client)?);
+    let router = Arc::new(Router::new(affinity_manager.clone())?);
+
+    // Keep usage totals on disk in case the daemon is killed
+    Router::start_usage_persist_task(router.clone());
 
     // Start config file watcher
     start_config_watcher(router.clone())?;
 
+    // Admin API (loopback by default, token-protected)
+    let admin_enabled =
+        match admin::spawn_admin_server(router.clone(), &proxy_config.admin, DEFAULT_BIND_ADDR)
+            .await
+        {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!("Admin API disabled: {}", e);
+                false
+            }
+        };
+
     // Start server
     println!("✨ cc-proxy is running!");
     println!("   Listening on:   http://{}", DEFAULT_BIND_ADDR);
     println!("   Share this URL: http://{}", advertise_addr);
     println!("   Claude Code: POST /v1/messages");
     println!("   Codex:       POST /responses");
+    if admin_enabled {
+        println!(
+            "   Admin API:   http://{}/admin{}",
+            proxy_config.admin.bind,
+            if proxy_config.admin.token.is_none() {
+                " (token in ~/.cc-proxy/admin_token)"
+            } else {
+                ""
+            }
+        );
+    }
     println!();
     println!("💡 Tip: Edit ~/.cc-proxy/provider.json to configure providers");
     println!();
 
-    // Run server (blocks until shutdown)
-    server::run_server(router, DEFAULT_BIND_ADDR).await?;
+    // Run server until it fails or we are asked to stop
+    tokio::select! {
+        result = server::run_server(router.clone(), DEFAULT_BIND_ADDR) => result?,
+        _ = shutdown_signal() => tracing::info!("Shutting down"),
+    }
 
     // Cleanup on shutdown
+    if let Err(e) = usage::write_usage_file(&router.usage_snapshot()) {
+        tracing::warn!("Failed to save usage totals: {}", e);
+    }
+    if let Some(path) = &affinity_path {
+        if let Err(e) = affinity_manager.save_snapshot(path).await {
+            tracing::warn!("Failed to save cache affinity: {}", e);
+        }
+    }
     remove_pid_file()?;
 
     Ok(())
 }
 
+/// Resolves on Ctrl-C or SIGTERM (sent by `cc-proxy stop`)
+async fn shutdown_signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = ctrl_c => {}
+                    _ = term.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = ctrl_c.await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = ctrl_c.await;
+    }
+}
+
 fn start_config_watcher(router: Arc<Router>) -> Result<()> {
     // Get config file path
     let config_path = provider::get_config_path()?;
@@ -199,24 +293,175 @@ fn stop_daemon() -> Result<()> {
     Ok(())
 }
 
-fn show_status() -> Result<()> {
+async fn show_status(json: bool) -> Result<()> {
     if !is_running() {
-        println!("Status: ❌ Not running");
+        if json {
+            println!("{}", serde_json::json!({ "running": false }));
+        } else {
+            println!("Status: ❌ Not running");
+        }
         return Ok(());
     }
 
     let pid = read_pid_file()?;
+    let admin_config = config::load_proxy_config()
+        .map(|config| config.admin)
+        .unwrap_or_default();
+    let status = match admin::fetch_status(&admin_config).await {
+        Ok(status) => status,
+        Err(e) => {
+            if json {
+                println!(
+                    "{}",
+                    serde_json::json!({ "running": true, "pid": pid, "error": e.to_string() })
+                );
+            } else {
+                println!("Status: ✅ Running");
+                println!("PID:    {}", pid);
+                println!("Details unavailable: {}", e);
+            }
+            return Ok(());
+        }
+    };
+
+    if json {
+        let mut value = serde_json::to_value(&status)?;
+        value["running"] = true.into();
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(());
+    }
+
     println!("Status: ✅ Running");
-    println!("PID:    {}", pid);
-    println!("Bind:   http://{}", DEFAULT_BIND_ADDR);
+    println!("PID:    {}", status.pid);
+    println!("Uptime: {}", format_duration(status.uptime_secs));
+    println!("Bind:   http://{}", status.listen);
+    println!("Share:  http://{}", detect_advertise_addr(&status.listen));
+
+    println!();
+    println!("Providers:");
+    let mut kinds: Vec<&str> = status.providers.iter().map(|p| p.kind.as_str()).collect();
+    kinds.sort();
+    kinds.dedup();
+    for kind in kinds {
+        println!("  {}:", kind);
+        for provider in status.providers.iter().filter(|p| p.kind == kind) {
+            let rate_limit = provider.rate_limit.as_ref();
+            let mut state = if !provider.enabled {
+                "disabled".to_string()
+            } else if let Some(secs) = rate_limit.and_then(|r| r.cooldown_secs) {
+                format!("rate limited, retry in {}s", secs)
+            } else {
+                match provider.circuit {
+                    BreakerState::Closed => "ok".to_string(),
+                    BreakerState::HalfOpen => "half-open (probing)".to_string(),
+                    BreakerState::Open => match provider.retry_in_secs {
+                        Some(secs) => format!("circuit open, retry in {}s", secs),
+                        None => "circuit open".to_string(),
+                    },
+                }
+            };
+            if rate_limit.is_some_and(|r| r.low) {
+                state.push_str(" (upstream quota low)");
+            }
+            if provider.cross_kind {
+                state.push_str(" (cross-kind fallback)");
+            }
+            println!(
+                "    [L{} w{}] {} - {}",
+                provider.level, provider.weight, provider.label, state
+            );
+        }
+    }
+
+    println!();
+    if status.affinity.is_empty() {
+        println!("Cache affinity: no active sessions");
+    } else {
+        println!("Cache affinity:");
+        for pin in &status.affinity {
+            println!(
+                "  {} -> {} ({} requests, expires in {})",
+                pin.key,
+                pin.provider,
+                pin.requests,
+                format_duration(pin.expires_in_secs)
+            );
+        }
+    }
+
+    println!();
+    if status.error_rates.is_empty() {
+        println!("Error rates (last 5 min): no traffic");
+    } else {
+        println!("Error rates (last 5 min):");
+        for rate in &status.error_rates {
+            println!(
+                "  {} - {}/{} failed ({:.0}%)",
+                rate.provider,
+                rate.failures,
+                rate.attempts,
+                rate.failures as f64 * 100.0 / rate.attempts.max(1) as f64
+            );
+        }
+    }
+
+    let usage = &status.usage;
+    let today = usage
+        .by_day
+        .get(&usage::today())
+        .cloned()
+        .unwrap_or_default();
+    println!();
+    println!(
+        "Today: {} requests, {} input / {} output / {} cache read / {} cache write tokens, ${:.2}",
+        today.requests,
+        today.usage.input_tokens,
+        today.usage.output_tokens,
+        today.usage.cache_read_tokens,
+        today.usage.cache_write_tokens,
+        today.cost_usd
+    );
     println!(
-        "Share:  http://{}",
-        detect_advertise_addr(DEFAULT_BIND_ADDR)
+        "Estimated spend: ${:.2} total (prompt cache saved ${:.2})",
+        usage.total_cost_usd, usage.cache_savings_usd
     );
+    for (provider, totals) in &usage.by_provider {
+        println!(
+            "  {} - {} requests, ${:.2}",
+            provider, totals.requests, totals.cost_usd
+        );
+    }
+
+    if !usage.recent_requests.is_empty() {
+        println!();
+        println!("Recent requests:");
+        for request in usage.recent_requests.iter().take(STATUS_RECENT_REQUESTS) {
+            let cost = request
+                .cost_usd
+                .map_or("unpriced".to_string(), |cost| format!("${:.4}", cost));
+            println!(
+                "  {} → {} ({}) - {} in / {} out tokens, {}",
+                request.model,
+                request.provider,
+                request.client,
+                request.usage.input_tokens,
+                request.usage.output_tokens,
+                cost
+            );
+        }
+    }
 
     Ok(())
 }
 
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
 fn print_help() {
     println!("cc-proxy - HTTP Proxy for Claude Code & Codex");
     println!();
@@ -226,7 +471,7 @@ fn print_help() {
     println!("COMMANDS:");
     println!("    start     Start the proxy daemon");
     println!("    stop      Stop the proxy daemon");
-    println!("    status    Show proxy status");
+    println!("    status    Show proxy status (--json for machine-readable output)");
     println!("    help      Show this help message");
     println!();
     println!("DESCRIPTION:");
@@ -236,8 +481,9 @@ fn print_help() {
     println!();
     println!("FEATURES:");
     println!("    • Model-aware routing (supports exact and wildcard matching)");
-    println!("    • Cache affinity (maintains provider for 5min for cache hits)");
+    println!("    • Cache affinity (sliding 5min, or 1h with the extended prompt cache)");
     println!("    • Automatic failover (tries multiple providers)");
+    println!("    • Circuit breaker (skips failing providers during a cooldown)");
     println!("    • Auto-configuration (sets up Claude Code & Codex)");
     println!();
     println!("CONFIGURATION:");
@@ -249,6 +495,7 @@ fn print_help() {
     println!();
     println!("    # Check if running");
     println!("    cc-proxy status");
+    println!("    cc-proxy status --json");
     println!();
     println!("    # Stop the proxy");
     println!("    cc-proxy stop");
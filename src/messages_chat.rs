@@ -0,0 +1,754 @@
+//! Anthropic Messages ⇄ OpenAI Chat Completions translation, for serving
+//! Claude Code from Chat Completions upstreams (DeepSeek, Qwen, OpenRouter,
+//! llama.cpp, ...)
+
+use crate::sse::{self, SseParser};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde_json::{json, Map, Value};
+
+/// Translate a `/v1/messages` request body into a `/chat/completions` one
+pub fn translate_request(request: &Value) -> Value {
+    let mut chat = Map::new();
+    chat.insert("model".into(), request["model"].clone());
+
+    let mut messages = Vec::new();
+    if let Some(system) = text_of(&request["system"]).filter(|s| !s.is_empty()) {
+        messages.push(json!({ "role": "system", "content": system }));
+    }
+    for message in request["messages"].as_array().into_iter().flatten() {
+        match message["role"].as_str() {
+            Some("assistant") => messages.push(assistant_message(&message["content"])),
+            _ => messages.extend(user_messages(&message["content"])),
+        }
+    }
+    chat.insert("messages".into(), messages.into());
+
+    for (from, to) in [
+        ("max_tokens", "max_tokens"),
+        ("temperature", "temperature"),
+        ("top_p", "top_p"),
+        ("stop_sequences", "stop"),
+    ] {
+        if !request[from].is_null() {
+            chat.insert(to.into(), request[from].clone());
+        }
+    }
+
+    if request["stream"].as_bool() == Some(true) {
+        chat.insert("stream".into(), true.into());
+        // Usage only arrives in a final chunk when asked for
+        chat.insert("stream_options".into(), json!({ "include_usage": true }));
+    }
+
+    // Server tools (web search, ...) have no schema and no Chat equivalent
+    let tools: Vec<Value> = request["tools"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|tool| tool["input_schema"].is_object())
+        .map(|tool| {
+            let mut function = json!({
+                "name": tool["name"],
+                "parameters": tool["input_schema"],
+            });
+            if let Some(description) = tool["description"].as_str() {
+                function["description"] = description.into();
+            }
+            json!({ "type": "function", "function": function })
+        })
+        .collect();
+    if !tools.is_empty() {
+        chat.insert("tools".into(), tools.into());
+        let choice = &request["tool_choice"];
+        match choice["type"].as_str() {
+            Some("auto") => {
+                chat.insert("tool_choice".into(), "auto".into());
+            }
+            Some("any") => {
+                chat.insert("tool_choice".into(), "required".into());
+            }
+            Some("none") => {
+                chat.insert("tool_choice".into(), "none".into());
+            }
+            Some("tool") => {
+                chat.insert(
+                    "tool_choice".into(),
+                    json!({ "type": "function", "function": { "name": choice["name"] } }),
+                );
+            }
+            _ => {}
+        }
+        if choice["disable_parallel_tool_use"].as_bool() == Some(true) {
+            chat.insert("parallel_tool_calls".into(), false.into());
+        }
+    }
+
+    Value::Object(chat)
+}
+
+/// Plain text of a string or an array of text blocks
+fn text_of(content: &Value) -> Option<String> {
+    match content {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(blocks) => Some(
+            blocks
+                .iter()
+                .filter_map(|block| block["text"].as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        _ => None,
+    }
+}
+
+fn image_part(block: &Value) -> Option<Value> {
+    let source = &block["source"];
+    let url = match source["type"].as_str()? {
+        "base64" => format!(
+            "data:{};base64,{}",
+            source["media_type"].as_str()?,
+            source["data"].as_str()?
+        ),
+        "url" => source["url"].as_str()?.to_string(),
+        _ => return None,
+    };
+    Some(json!({ "type": "image_url", "image_url": { "url": url } }))
+}
+
+/// A user turn becomes one `tool` message per tool result, then the user message
+fn user_messages(content: &Value) -> Vec<Value> {
+    let Some(blocks) = content.as_array() else {
+        return vec![json!({ "role": "user", "content": content })];
+    };
+
+    let mut messages = Vec::new();
+    let mut parts = Vec::new();
+    for block in blocks {
+        match block["type"].as_str() {
+            Some("text") => parts.push(json!({ "type": "text", "text": block["text"] })),
+            Some("image") => parts.extend(image_part(block)),
+            Some("tool_result") => {
+                let content = &block["content"];
+                messages.push(json!({
+                    "role": "tool",
+                    "tool_call_id": block["tool_use_id"],
+                    "content": text_of(content).unwrap_or_default(),
+                }));
+                // Chat tool messages are text-only; images follow as user content
+                if let Some(nested) = content.as_array() {
+                    parts.extend(nested.iter().filter_map(image_part));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if parts.iter().all(|part| part["type"] == "text") {
+        if !parts.is_empty() {
+            let text: Vec<&str> = parts.iter().filter_map(|p| p["text"].as_str()).collect();
+            messages.push(json!({ "role": "user", "content": text.join("\n") }));
+        }
+    } else {
+        messages.push(json!({ "role": "user", "content": parts }));
+    }
+    messages
+}
+
+/// Text and tool calls of an assistant turn; thinking blocks are dropped
+fn assistant_message(content: &Value) -> Value {
+    let Some(blocks) = content.as_array() else {
+        return json!({ "role": "assistant", "content": content });
+    };
+
+    let mut text = Vec::new();
+    let mut tool_calls = Vec::new();
+    for block in blocks {
+        match block["type"].as_str() {
+            Some("text") => text.extend(block["text"].as_str()),
+            Some("tool_use") => tool_calls.push(json!({
+                "id": block["id"],
+                "type": "function",
+                "function": {
+                    "name": block["name"],
+                    "arguments": block["input"].to_string(),
+                },
+            })),
+            _ => {}
+        }
+    }
+
+    let mut message = json!({ "role": "assistant", "content": Value::Null });
+    if !text.is_empty() {
+        message["content"] = text.join("\n").into();
+    }
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = tool_calls.into();
+    }
+    message
+}
+
+fn stop_reason(finish_reason: &str, used_tools: bool) -> &'static str {
+    match finish_reason {
+        "length" => "max_tokens",
+        "tool_calls" | "function_call" => "tool_use",
+        "content_filter" => "refusal",
+        _ if used_tools => "tool_use",
+        _ => "end_turn",
+    }
+}
+
+/// Anthropic usage from a Chat Completions `usage` object
+fn usage_of(usage: &Value) -> Value {
+    let cached = usage["prompt_tokens_details"]["cached_tokens"]
+        .as_u64()
+        .unwrap_or(0);
+    let prompt = usage["prompt_tokens"].as_u64().unwrap_or(0);
+    json!({
+        "input_tokens": prompt.saturating_sub(cached),
+        "output_tokens": usage["completion_tokens"].as_u64().unwrap_or(0),
+        "cache_read_input_tokens": cached,
+    })
+}
+
+fn message_id(chat_id: &Value) -> String {
+    match chat_id.as_str() {
+        Some(id) => format!("msg_{}", id),
+        None => format!("msg_{}", hex::encode(rand::random::<[u8; 12]>())),
+    }
+}
+
+/// Translate a buffered `/chat/completions` response into a Messages response
+pub fn translate_response(body: &[u8]) -> Option<Bytes> {
+    let chat: Value = serde_json::from_slice(body).ok()?;
+    let choice = chat["choices"].get(0)?;
+    let message = &choice["message"];
+
+    let mut content = Vec::new();
+    let reasoning = message["reasoning_content"]
+        .as_str()
+        .or_else(|| message["reasoning"].as_str());
+    if let Some(thinking) = reasoning.filter(|t| !t.is_empty()) {
+        content.push(json!({ "type": "thinking", "thinking": thinking, "signature": "" }));
+    }
+    if let Some(text) = message["content"].as_str().filter(|t| !t.is_empty()) {
+        content.push(json!({ "type": "text", "text": text }));
+    }
+    let tool_calls = message["tool_calls"].as_array();
+    for call in tool_calls.into_iter().flatten() {
+        let arguments = call["function"]["arguments"].as_str().unwrap_or("{}");
+        content.push(json!({
+            "type": "tool_use",
+            "id": call["id"],
+            "name": call["function"]["name"],
+            "input": serde_json::from_str::<Value>(arguments).unwrap_or_else(|_| json!({})),
+        }));
+    }
+
+    let used_tools = tool_calls.is_some_and(|calls| !calls.is_empty());
+    let response = json!({
+        "id": message_id(&chat["id"]),
+        "type": "message",
+        "role": "assistant",
+        "model": chat["model"],
+        "content": content,
+        "stop_reason": stop_reason(choice["finish_reason"].as_str().unwrap_or("stop"), used_tools),
+        "stop_sequence": null,
+        "usage": usage_of(&chat["usage"]),
+    });
+    serde_json::to_vec(&response).ok().map(Bytes::from)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Block {
+    Thinking,
+    Text,
+    /// Chat `tool_calls[].index` of the call streaming into this block
+    Tool(u64),
+}
+
+/// Rebuilds the Messages event sequence from Chat Completions chunks
+#[derive(Default)]
+struct StreamTranslator {
+    parser: SseParser,
+    started: bool,
+    finished: bool,
+    /// Open content block and its index
+    block: Option<(Block, usize)>,
+    next_index: usize,
+    used_tools: bool,
+    finish_reason: Option<String>,
+    usage: Value,
+}
+
+impl StreamTranslator {
+    fn feed(&mut self, chunk: &[u8]) -> Bytes {
+        let mut out = String::new();
+        for event in self.parser.feed(chunk) {
+            if self.finished {
+                continue;
+            }
+            if event.data.trim() == "[DONE]" {
+                self.close(&mut out);
+                continue;
+            }
+            if let Some(chunk) = event.json() {
+                self.chunk(&chunk, &mut out);
+            }
+        }
+        Bytes::from(out)
+    }
+
+    /// Called when the upstream stream ends
+    fn finish(&mut self) -> Bytes {
+        let mut out = String::new();
+        if !self.finished {
+            if self.finish_reason.is_some() {
+                self.close(&mut out);
+            } else {
+                self.finished = true;
+                out.push_str(&String::from_utf8_lossy(&sse::error_event(
+                    "claude",
+                    "api_error",
+                    "Upstream stream ended unexpectedly",
+                )));
+            }
+        }
+        Bytes::from(out)
+    }
+
+    fn chunk(&mut self, chunk: &Value, out: &mut String) {
+        if chunk["error"].is_object() {
+            let error = &chunk["error"];
+            let error_type = error["type"]
+                .as_str()
+                .or_else(|| error["code"].as_str())
+                .unwrap_or("api_error");
+            let message = error["message"].as_str().unwrap_or("Upstream error");
+            out.push_str(&String::from_utf8_lossy(&sse::error_event(
+                "claude", error_type, message,
+            )));
+            self.finished = true;
+            return;
+        }
+
+        if !self.started {
+            self.started = true;
+            emit(
+                out,
+                "message_start",
+                json!({
+                    "type": "message_start",
+                    "message": {
+                        "id": message_id(&chunk["id"]),
+                        "type": "message",
+                        "role": "assistant",
+                        "model": chunk["model"],
+                        "content": [],
+                        "stop_reason": null,
+                        "stop_sequence": null,
+                        "usage": { "input_tokens": 0, "output_tokens": 0 },
+                    },
+                }),
+            );
+        }
+
+        if chunk["usage"].is_object() {
+            self.usage = usage_of(&chunk["usage"]);
+        }
+
+        let Some(choice) = chunk["choices"].get(0) else {
+            return;
+        };
+        let delta = &choice["delta"];
+
+        let reasoning = delta["reasoning_content"]
+            .as_str()
+            .or_else(|| delta["reasoning"].as_str());
+        if let Some(thinking) = reasoning.filter(|t| !t.is_empty()) {
+            let index = self.open(Block::Thinking, out);
+            emit_delta(
+                out,
+                index,
+                json!({ "type": "thinking_delta", "thinking": thinking }),
+            );
+        }
+
+        if let Some(text) = delta["content"].as_str().filter(|t| !t.is_empty()) {
+            let index = self.open(Block::Text, out);
+            emit_delta(out, index, json!({ "type": "text_delta", "text": text }));
+        }
+
+        for call in delta["tool_calls"].as_array().into_iter().flatten() {
+            let call_index = call["index"].as_u64().unwrap_or(0);
+            let index = match self.block {
+                Some((Block::Tool(open), index)) if open == call_index => index,
+                _ => {
+                    self.used_tools = true;
+                    self.close_block(out);
+                    let index = self.next_index;
+                    self.next_index += 1;
+                    self.block = Some((Block::Tool(call_index), index));
+                    emit(
+                        out,
+                        "content_block_start",
+                        json!({
+                            "type": "content_block_start",
+                            "index": index,
+                            "content_block": {
+                                "type": "tool_use",
+                                "id": call["id"],
+                                "name": call["function"]["name"],
+                                "input": {},
+                            },
+                        }),
+                    );
+                    index
+                }
+            };
+            if let Some(arguments) = call["function"]["arguments"]
+                .as_str()
+                .filter(|a| !a.is_empty())
+            {
+                emit_delta(
+                    out,
+                    index,
+                    json!({ "type": "input_json_delta", "partial_json": arguments }),
+                );
+            }
+        }
+
+        if let Some(reason) = choice["finish_reason"].as_str() {
+            self.finish_reason = Some(reason.to_string());
+        }
+    }
+
+    /// Index of the open block of this type, starting a new block if needed
+    fn open(&mut self, block: Block, out: &mut String) -> usize {
+        if let Some((open, index)) = self.block {
+            if open == block {
+                return index;
+            }
+        }
+        self.close_block(out);
+
+        let index = self.next_index;
+        self.next_index += 1;
+        self.block = Some((block, index));
+        let content_block = match block {
+            Block::Thinking => json!({ "type": "thinking", "thinking": "", "signature": "" }),
+            _ => json!({ "type": "text", "text": "" }),
+        };
+        emit(
+            out,
+            "content_block_start",
+            json!({ "type": "content_block_start", "index": index, "content_block": content_block }),
+        );
+        index
+    }
+
+    fn close_block(&mut self, out: &mut String) {
+        if let Some((_, index)) = self.block.take() {
+            emit(
+                out,
+                "content_block_stop",
+                json!({ "type": "content_block_stop", "index": index }),
+            );
+        }
+    }
+
+    fn close(&mut self, out: &mut String) {
+        if !self.started {
+            self.chunk(&json!({}), out);
+        }
+        self.close_block(out);
+
+        let reason = stop_reason(
+            self.finish_reason.as_deref().unwrap_or("stop"),
+            self.used_tools,
+        );
+        let usage = if self.usage.is_object() {
+            self.usage.clone()
+        } else {
+            json!({ "output_tokens": 0 })
+        };
+        emit(
+            out,
+            "message_delta",
+            json!({
+                "type": "message_delta",
+                "delta": { "stop_reason": reason, "stop_sequence": null },
+                "usage": usage,
+            }),
+        );
+        emit(out, "message_stop", json!({ "type": "message_stop" }));
+        self.finished = true;
+    }
+}
+
+fn emit(out: &mut String, event: &str, data: Value) {
+    out.push_str(&format!("event: {}\ndata: {}\n\n", event, data));
+}
+
+fn emit_delta(out: &mut String, index: usize, delta: Value) {
+    emit(
+        out,
+        "content_block_delta",
+        json!({ "type": "content_block_delta", "index": index, "delta": delta }),
+    );
+}
+
+/// Translate a `/chat/completions` SSE stream into Messages SSE events
+pub fn translate_stream<S, E>(stream: S) -> impl Stream<Item = Result<Bytes, E>> + Send
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+    E: Send + 'static,
+{
+    futures::stream::unfold(
+        Some((stream, StreamTranslator::default())),
+        |state| async move {
+            let (mut stream, mut translator) = state?;
+            match stream.next().await {
+                Some(Ok(chunk)) => Some((Ok(translator.feed(&chunk)), Some((stream, translator)))),
+                Some(Err(e)) => Some((Err(e), Some((stream, translator)))),
+                None => Some((Ok(translator.finish()), None)),
+            }
+        },
+    )
+}
+
+/// Whether a Messages request replays thinking without a signature, as
+/// produced by `translate_response` / `translate_stream`
+pub fn has_unsigned_thinking(body: &[u8]) -> bool {
+    [&b"\"signature\":\"\""[..], b"\"signature\": \"\""]
+        .iter()
+        .any(|marker| body.windows(marker.len()).any(|window| window == *marker))
+}
+
+/// Drop unsigned thinking from a Messages request bound for an Anthropic
+/// upstream, which rejects it. Returns whether anything was removed.
+pub fn strip_unsigned_thinking(request: &mut Value) -> bool {
+    let Some(messages) = request["messages"].as_array_mut() else {
+        return false;
+    };
+    let mut stripped = false;
+    for message in messages.iter_mut().filter(|m| m["role"] == "assistant") {
+        let Some(blocks) = message["content"].as_array_mut() else {
+            continue;
+        };
+        let before = blocks.len();
+        blocks.retain(|block| {
+            !(block["type"] == "thinking"
+                && block["signature"].as_str().unwrap_or_default().is_empty())
+        });
+        stripped |= blocks.len() != before;
+    }
+
+    // A tool-use turn being continued must start with thinking while it is on
+    let continues_without_thinking = messages
+        .iter()
+        .rev()
+        .find(|m| m["role"] == "assistant")
+        .and_then(|m| m["content"].as_array())
+        .is_some_and(|blocks| {
+            blocks.iter().any(|b| b["type"] == "tool_use")
+                && !blocks
+                    .first()
+                    .is_some_and(|b| b["type"] == "thinking" || b["type"] == "redacted_thinking")
+        });
+    if stripped && continues_without_thinking {
+        if let Some(request) = request.as_object_mut() {
+            request.remove("thinking");
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sse::SseEvent;
+
+    #[test]
+    fn translates_request_with_tools_images_and_results() {
+        let request = json!({
+            "model": "deepseek-chat",
+            "max_tokens": 1024,
+            "stream": true,
+            "system": [{ "type": "text", "text": "Be brief.", "cache_control": { "type": "ephemeral" } }],
+            "tools": [
+                { "name": "read", "description": "Read a file", "input_schema": { "type": "object" } },
+                { "type": "web_search_20250305", "name": "web_search" }
+            ],
+            "tool_choice": { "type": "any" },
+            "messages": [
+                { "role": "user", "content": [
+                    { "type": "text", "text": "What is in this?" },
+                    { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "AAAA" } }
+                ] },
+                { "role": "assistant", "content": [
+                    { "type": "thinking", "thinking": "hmm", "signature": "x" },
+                    { "type": "text", "text": "Let me look." },
+                    { "type": "tool_use", "id": "toolu_1", "name": "read", "input": { "path": "a.txt" } }
+                ] },
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "toolu_1", "content": [{ "type": "text", "text": "hello" }] },
+                    { "type": "text", "text": "Thanks" }
+                ] }
+            ]
+        });
+
+        let chat = translate_request(&request);
+        assert_eq!(chat["stream_options"]["include_usage"], true);
+        assert_eq!(chat["tool_choice"], "required");
+        assert_eq!(chat["tools"].as_array().unwrap().len(), 1);
+        assert_eq!(chat["tools"][0]["function"]["parameters"]["type"], "object");
+
+        let messages = chat["messages"].as_array().unwrap();
+        assert_eq!(
+            messages[0],
+            json!({ "role": "system", "content": "Be brief." })
+        );
+        assert_eq!(
+            messages[1]["content"][1]["image_url"]["url"],
+            "data:image/png;base64,AAAA"
+        );
+        assert_eq!(messages[2]["content"], "Let me look.");
+        assert_eq!(
+            messages[2]["tool_calls"][0]["function"]["arguments"],
+            "{\"path\":\"a.txt\"}"
+        );
+        assert_eq!(
+            messages[3],
+            json!({ "role": "tool", "tool_call_id": "toolu_1", "content": "hello" })
+        );
+        assert_eq!(messages[4], json!({ "role": "user", "content": "Thanks" }));
+    }
+
+    #[test]
+    fn translates_buffered_response() {
+        let body = json!({
+            "id": "chatcmpl-1",
+            "model": "deepseek-chat",
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": "Reading it.",
+                    "tool_calls": [{ "id": "call_1", "type": "function", "function": { "name": "read", "arguments": "{\"path\":\"a.txt\"}" } }]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": { "prompt_tokens": 100, "completion_tokens": 20, "prompt_tokens_details": { "cached_tokens": 60 } }
+        });
+
+        let message: Value =
+            serde_json::from_slice(&translate_response(body.to_string().as_bytes()).unwrap())
+                .unwrap();
+        assert_eq!(message["id"], "msg_chatcmpl-1");
+        assert_eq!(message["content"][0]["text"], "Reading it.");
+        assert_eq!(message["content"][1]["input"]["path"], "a.txt");
+        assert_eq!(message["stop_reason"], "tool_use");
+        assert_eq!(message["usage"]["input_tokens"], 40);
+        assert_eq!(message["usage"]["cache_read_input_tokens"], 60);
+    }
+
+    fn events(bytes: &[u8]) -> Vec<SseEvent> {
+        SseParser::new().feed(bytes)
+    }
+
+    #[tokio::test]
+    async fn translates_stream_into_message_events() {
+        let chunks = [
+            r#"data: {"id":"c1","model":"m","choices":[{"index":0,"delta":{"role":"assistant","reasoning_content":"think"}}]}"#,
+            r#"data: {"id":"c1","model":"m","choices":[{"index":0,"delta":{"content":"Hi"}}]}"#,
+            r#"data: {"id":"c1","model":"m","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"read","arguments":""}}]}}]}"#,
+            r#"data: {"id":"c1","model":"m","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"path\":1}"}}]}}]}"#,
+            r#"data: {"id":"c1","model":"m","choices":[{"index":0,"delta":{},"finish_reason":"tool_calls"}]}"#,
+            r#"data: {"id":"c1","model":"m","choices":[],"usage":{"prompt_tokens":10,"completion_tokens":5}}"#,
+            "data: [DONE]",
+        ];
+        let stream = futures::stream::iter(
+            chunks
+                .iter()
+                .map(|c| Ok::<_, std::io::Error>(Bytes::from(format!("{}\n\n", c))))
+                .collect::<Vec<_>>(),
+        );
+        let out: Vec<u8> = translate_stream(stream)
+            .map(|chunk| chunk.unwrap().to_vec())
+            .concat()
+            .await;
+
+        let events = events(&out);
+        let types: Vec<String> = events.iter().filter_map(|e| e.event.clone()).collect();
+        assert_eq!(
+            types,
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        let tool_start = events[7].json().unwrap();
+        assert_eq!(tool_start["index"], 2);
+        assert_eq!(tool_start["content_block"]["name"], "read");
+        assert_eq!(
+            events[8].json().unwrap()["delta"]["partial_json"],
+            "{\"path\":1}"
+        );
+        let delta = events[10].json().unwrap();
+        assert_eq!(delta["delta"]["stop_reason"], "tool_use");
+        assert_eq!(delta["usage"]["output_tokens"], 5);
+    }
+
+    #[tokio::test]
+    async fn truncated_stream_ends_with_error_event() {
+        let stream = futures::stream::iter(vec![Ok::<_, std::io::Error>(Bytes::from(
+            "data: {\"id\":\"c1\",\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n",
+        ))]);
+        let out: Vec<u8> = translate_stream(stream)
+            .map(|chunk| chunk.unwrap().to_vec())
+            .concat()
+            .await;
+
+        let last = events(&out).pop().unwrap();
+        assert_eq!(last.event.as_deref(), Some("error"));
+    }
+
+    #[test]
+    fn strips_unsigned_thinking_for_anthropic() {
+        let mut request = json!({
+            "thinking": { "type": "enabled", "budget_tokens": 4096 },
+            "messages": [
+                { "role": "user", "content": "hi" },
+                { "role": "assistant", "content": [
+                    { "type": "thinking", "thinking": "signed", "signature": "sig" },
+                    { "type": "text", "text": "Hello" }
+                ] },
+                { "role": "user", "content": "read a.txt" },
+                { "role": "assistant", "content": [
+                    { "type": "thinking", "thinking": "translated", "signature": "" },
+                    { "type": "tool_use", "id": "toolu_1", "name": "read", "input": {} }
+                ] },
+                { "role": "user", "content": [{ "type": "tool_result", "tool_use_id": "toolu_1", "content": "x" }] }
+            ]
+        });
+        let body = serde_json::to_vec(&request).unwrap();
+
+        assert!(has_unsigned_thinking(&body));
+        assert!(strip_unsigned_thinking(&mut request));
+        assert_eq!(request["messages"][1]["content"][0]["signature"], "sig");
+        assert_eq!(request["messages"][3]["content"][0]["type"], "tool_use");
+        // The continued tool-use turn no longer starts with thinking
+        assert!(request.get("thinking").is_none());
+        assert!(!strip_unsigned_thinking(&mut request));
+    }
+}
@@ -0,0 +1,361 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Latency buckets in seconds; streams commit after the first content event,
+/// so slow first tokens land in the upper buckets
+const LATENCY_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Window for the recent error rates shown by `cc-proxy status`
+pub const ERROR_RATE_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// `(kind, model, provider)`
+type RouteLabels = (String, String, String);
+
+#[derive(Default, Clone)]
+struct Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; LATENCY_BUCKETS.len()];
+        }
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if secs <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+/// Request-path counters for the Prometheus `/metrics` endpoint
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<HashMap<(RouteLabels, &'static str), u64>>,
+    latency: Mutex<HashMap<RouteLabels, Histogram>>,
+    failovers: Mutex<HashMap<(String, String, String), u64>>,
+    inflight: Mutex<HashMap<String, u64>>,
+    // Attempt outcomes within `ERROR_RATE_WINDOW`, per provider
+    recent: Mutex<HashMap<String, VecDeque<(Instant, bool)>>>,
+}
+
+/// Attempts and failures of one provider within `ERROR_RATE_WINDOW`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorRate {
+    pub provider: String,
+    pub attempts: u64,
+    pub failures: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a finished routing decision. `outcome` is `success`,
+    /// `non_retryable` or `failed` (no provider produced a response).
+    pub fn record_request(
+        &self,
+        kind: &str,
+        model: &str,
+        provider: &str,
+        outcome: &'static str,
+        elapsed: Duration,
+    ) {
+        let labels = (kind.to_string(), model.to_string(), provider.to_string());
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((labels.clone(), outcome))
+            .or_default() += 1;
+        self.latency
+            .lock()
+            .unwrap()
+            .entry(labels)
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Count a provider attempt that was abandoned for the next one
+    pub fn record_failover(&self, kind: &str, provider: &str, reason: &str) {
+        *self
+            .failovers
+            .lock()
+            .unwrap()
+            .entry((kind.to_string(), provider.to_string(), reason.to_string()))
+            .or_default() += 1;
+    }
+
+    /// Remember the outcome of one provider attempt for the recent error rate
+    pub fn record_attempt(&self, provider: &str, ok: bool) {
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        let outcomes = recent.entry(provider.to_string()).or_default();
+        outcomes.push_back((now, ok));
+        prune(outcomes, now);
+    }
+
+    /// Per-provider attempts and failures within `ERROR_RATE_WINDOW`
+    pub fn error_rates(&self) -> Vec<ErrorRate> {
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        recent.retain(|_, outcomes| {
+            prune(outcomes, now);
+            !outcomes.is_empty()
+        });
+
+        let mut rates: Vec<ErrorRate> = recent
+            .iter()
+            .map(|(provider, outcomes)| ErrorRate {
+                provider: provider.clone(),
+                attempts: outcomes.len() as u64,
+                failures: outcomes.iter().filter(|(_, ok)| !ok).count() as u64,
+            })
+            .collect();
+        rates.sort_by(|a, b| a.provider.cmp(&b.provider));
+        rates
+    }
+
+    /// Mark a response stream as in flight until the returned guard is dropped
+    pub fn stream_started(self: &Arc<Self>, kind: &str) -> InflightGuard {
+        *self
+            .inflight
+            .lock()
+            .unwrap()
+            .entry(kind.to_string())
+            .or_default() += 1;
+        InflightGuard {
+            metrics: self.clone(),
+            kind: kind.to_string(),
+        }
+    }
+
+    /// Render the request-path metrics in Prometheus text format
+    pub fn render(&self, out: &mut String) {
+        header(
+            out,
+            "cc_proxy_requests_total",
+            "counter",
+            "Requests by kind, model, serving provider and outcome",
+        );
+        let requests = self.requests.lock().unwrap();
+        for (((kind, model, provider), outcome), count) in sorted(&requests) {
+            sample(
+                out,
+                "cc_proxy_requests_total",
+                &[
+                    ("kind", kind),
+                    ("model", model),
+                    ("provider", provider),
+                    ("outcome", outcome),
+                ],
+                *count as f64,
+            );
+        }
+        drop(requests);
+
+        header(
+            out,
+            "cc_proxy_request_duration_seconds",
+            "histogram",
+            "Time until the response was committed to the client",
+        );
+        let latency = self.latency.lock().unwrap();
+        for ((kind, model, provider), histogram) in sorted(&latency) {
+            let labels = [
+                ("kind", kind.as_str()),
+                ("model", model),
+                ("provider", provider),
+            ];
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+                let le = bound.to_string();
+                let mut bucket_labels = labels.to_vec();
+                bucket_labels.push(("le", &le));
+                sample(
+                    out,
+                    "cc_proxy_request_duration_seconds_bucket",
+                    &bucket_labels,
+                    *count as f64,
+                );
+            }
+            let mut inf_labels = labels.to_vec();
+            inf_labels.push(("le", "+Inf"));
+            sample(
+                out,
+                "cc_proxy_request_duration_seconds_bucket",
+                &inf_labels,
+                histogram.count as f64,
+            );
+            sample(
+                out,
+                "cc_proxy_request_duration_seconds_sum",
+                &labels,
+                histogram.sum,
+            );
+            sample(
+                out,
+                "cc_proxy_request_duration_seconds_count",
+                &labels,
+                histogram.count as f64,
+            );
+        }
+        drop(latency);
+
+        header(
+            out,
+            "cc_proxy_failovers_total",
+            "counter",
+            "Provider attempts abandoned for the next candidate",
+        );
+        let failovers = self.failovers.lock().unwrap();
+        for ((kind, provider, reason), count) in sorted(&failovers) {
+            sample(
+                out,
+                "cc_proxy_failovers_total",
+                &[("kind", kind), ("provider", provider), ("reason", reason)],
+                *count as f64,
+            );
+        }
+        drop(failovers);
+
+        header(
+            out,
+            "cc_proxy_inflight_streams",
+            "gauge",
+            "Response bodies currently streaming to clients",
+        );
+        let inflight = self.inflight.lock().unwrap();
+        for (kind, count) in sorted(&inflight) {
+            sample(
+                out,
+                "cc_proxy_inflight_streams",
+                &[("kind", kind)],
+                *count as f64,
+            );
+        }
+    }
+}
+
+pub struct InflightGuard {
+    metrics: Arc<Metrics>,
+    kind: String,
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        if let Some(count) = self.metrics.inflight.lock().unwrap().get_mut(&self.kind) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+fn prune(outcomes: &mut VecDeque<(Instant, bool)>, now: Instant) {
+    while outcomes
+        .front()
+        .is_some_and(|(at, _)| now.duration_since(*at) > ERROR_RATE_WINDOW)
+    {
+        outcomes.pop_front();
+    }
+}
+
+/// Stable output order keeps scrapes diffable
+fn sorted<K: Ord, V>(map: &HashMap<K, V>) -> BTreeMap<&K, &V> {
+    map.iter().collect()
+}
+
+pub fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+pub fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: f64) {
+    out.push_str(name);
+    if !labels.is_empty() {
+        out.push('{');
+        for (i, (key, value)) in labels.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{}=\"{}\"", key, escape_label(value));
+        }
+        out.push('}');
+    }
+    let _ = writeln!(out, " {}", value);
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counters_and_histograms() {
+        let metrics = Metrics::new();
+        metrics.record_request(
+            "claude",
+            "claude-sonnet-4-5",
+            "relay",
+            "success",
+            Duration::from_millis(300),
+        );
+        metrics.record_failover("claude", "backup", "529");
+
+        let mut out = String::new();
+        metrics.render(&mut out);
+
+        assert!(out.contains("# TYPE cc_proxy_requests_total counter"));
+        assert!(out.contains(
+            "cc_proxy_requests_total{kind=\"claude\",model=\"claude-sonnet-4-5\",provider=\"relay\",outcome=\"success\"} 1"
+        ));
+        assert!(out.contains("le=\"0.25\"} 0"));
+        assert!(out.contains("le=\"0.5\"} 1"));
+        assert!(out.contains("le=\"+Inf\"} 1"));
+        assert!(out.contains(
+            "cc_proxy_failovers_total{kind=\"claude\",provider=\"backup\",reason=\"529\"} 1"
+        ));
+    }
+
+    #[test]
+    fn inflight_guard_decrements_on_drop() {
+        let metrics = Arc::new(Metrics::new());
+        let guard = metrics.stream_started("codex");
+        assert_eq!(metrics.inflight.lock().unwrap()["codex"], 1);
+        drop(guard);
+        assert_eq!(metrics.inflight.lock().unwrap()["codex"], 0);
+    }
+
+    #[test]
+    fn error_rates_count_recent_attempts() {
+        let metrics = Metrics::new();
+        metrics.record_attempt("a", true);
+        metrics.record_attempt("a", false);
+        metrics.record_attempt("b", true);
+
+        let rates = metrics.error_rates();
+        assert_eq!(rates.len(), 2);
+        assert_eq!((rates[0].attempts, rates[0].failures), (2, 1));
+        assert_eq!(rates[1].failures, 0);
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        let mut out = String::new();
+        sample(&mut out, "m", &[("provider", "a \"b\"\\c")], 2.0);
+        assert_eq!(out, "m{provider=\"a \\\"b\\\"\\\\c\"} 2\n");
+    }
+}
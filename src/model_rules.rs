@@ -0,0 +1,355 @@
+use crate::sse::{SseEvent, SseParser};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Match `text` against a glob pattern where `*` matches any run of
+/// characters and `?` a single character. Returns the text captured by each `*`.
+pub fn glob_captures(pattern: &str, text: &str) -> Option<Vec<String>> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // Text range captured by each `*` seen so far
+    let mut captures: Vec<(usize, usize)> = Vec::new();
+    // The last `*`: only it needs to grow on a mismatch, since earlier ones
+    // already took the shortest capture that lets the pattern up to it match
+    let mut last_star: Option<usize> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                captures.push((t, t));
+                last_star = Some(p);
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                t += 1;
+            }
+            Some(c) if *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => {
+                let star = last_star?;
+                let capture = captures.last_mut()?;
+                capture.1 += 1;
+                p = star + 1;
+                t = capture.1;
+            }
+        }
+    }
+    for c in &pattern[p..] {
+        if *c != '*' {
+            return None;
+        }
+        captures.push((t, t));
+    }
+
+    Some(
+        captures
+            .into_iter()
+            .map(|(start, end)| text[start..end].iter().collect())
+            .collect(),
+    )
+}
+
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    glob_captures(pattern, text).is_some()
+}
+
+/// Per-provider model rewriting (`modelMap` in provider.json).
+/// Keys are exact names or glob patterns; a `*` in the target is replaced
+/// with the text matched by the first `*` in the pattern.
+#[derive(Debug, Clone, Default)]
+pub struct ModelMap {
+    rules: Vec<(String, String)>,
+}
+
+impl ModelMap {
+    pub fn new(map: &HashMap<String, String>) -> Self {
+        let mut rules: Vec<(String, String)> =
+            map.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        // Exact names first, then more specific (longer) patterns
+        rules.sort_by(|(a, _), (b, _)| {
+            let key = |p: &String| (p.contains(['*', '?']), std::cmp::Reverse(p.len()));
+            key(a).cmp(&key(b)).then_with(|| a.cmp(b))
+        });
+        Self { rules }
+    }
+
+    /// Upstream model name for a requested model, if a rule applies
+    pub fn map(&self, model: &str) -> Option<String> {
+        self.rules.iter().find_map(|(pattern, target)| {
+            let captures = glob_captures(pattern, model)?;
+            Some(match captures.first() {
+                Some(capture) if target.contains('*') => target.replacen('*', capture, 1),
+                _ => target.clone(),
+            })
+        })
+    }
+}
+
+/// Which requested models a provider serves (`models` / `excludeModels`)
+#[derive(Debug, Clone, Default)]
+pub struct ModelFilter {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl ModelFilter {
+    /// An empty allow-list serves every model; the deny-list always wins
+    pub fn allows(&self, model: &str) -> bool {
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|p| glob_match(p, model));
+        allowed && !self.deny.iter().any(|p| glob_match(p, model))
+    }
+}
+
+/// Replace the `model` field of a request body
+pub fn rewrite_request_model(request: &Value, model: &str) -> Option<Bytes> {
+    let mut request = request.clone();
+    request
+        .as_object_mut()?
+        .insert("model".into(), model.into());
+    serde_json::to_vec(&request).ok().map(Bytes::from)
+}
+
+/// Set every response-level `model` field (top-level, `message.model` in
+/// Anthropic `message_start`, `response.model` in Responses events)
+fn restore_model_fields(value: &mut Value, model: &str) -> bool {
+    let mut changed = false;
+    if value.get("model").is_some_and(Value::is_string) {
+        value["model"] = model.into();
+        changed = true;
+    }
+    for key in ["message", "response"] {
+        if let Some(nested) = value.get_mut(key) {
+            if nested.get("model").is_some_and(Value::is_string) {
+                nested["model"] = model.into();
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Map the model name back in a buffered JSON response body
+pub fn restore_json_model(body: &[u8], model: &str) -> Option<Bytes> {
+    let mut value: Value = serde_json::from_slice(body).ok()?;
+    if !restore_model_fields(&mut value, model) {
+        return None;
+    }
+    serde_json::to_vec(&value).ok().map(Bytes::from)
+}
+
+fn encode_event(event: &SseEvent) -> String {
+    let mut out = String::new();
+    if let Some(name) = &event.event {
+        out.push_str("event: ");
+        out.push_str(name);
+        out.push('\n');
+    }
+    for line in event.data.split('\n') {
+        out.push_str("data: ");
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push('\n');
+    out
+}
+
+/// Map the model name back in an SSE stream. Only events whose `model`
+/// changed are re-encoded; everything else passes through byte for byte.
+pub fn restore_sse_model<S, E>(
+    stream: S,
+    model: String,
+) -> impl Stream<Item = Result<Bytes, E>> + Send
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+    E: Send + 'static,
+{
+    futures::stream::unfold(Some((stream, SseParser::new())), move |state| {
+        let model = model.clone();
+        async move {
+            let (mut stream, mut parser) = state?;
+            match stream.next().await {
+                Some(Ok(chunk)) => {
+                    let mut out = Vec::with_capacity(chunk.len());
+                    for (event, raw) in parser.feed_raw(&chunk) {
+                        match event.and_then(|event| restore_event(event, &model)) {
+                            Some(encoded) => out.extend_from_slice(encoded.as_bytes()),
+                            None => out.extend_from_slice(&raw),
+                        }
+                    }
+                    Some((Ok(Bytes::from(out)), Some((stream, parser))))
+                }
+                Some(Err(e)) => Some((Err(e), Some((stream, parser)))),
+                // An unfinished last event is passed on as it was
+                None => Some((Ok(Bytes::from(parser.take_remainder())), None)),
+            }
+        }
+    })
+}
+
+/// The event re-encoded with the model restored, if it named one
+fn restore_event(mut event: SseEvent, model: &str) -> Option<String> {
+    if !event.data.contains("\"model\"") {
+        return None;
+    }
+    let mut value = event.json()?;
+    if !restore_model_fields(&mut value, model) {
+        return None;
+    }
+    event.data = value.to_string();
+    Some(encode_event(&event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn model_map(pairs: &[(&str, &str)]) -> ModelMap {
+        ModelMap::new(
+            &pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_match("claude-opus-*", "claude-opus-4-1"));
+        assert!(glob_match("gpt-5*", "gpt-5"));
+        assert!(glob_match("gpt-?", "gpt-5"));
+        assert!(!glob_match("claude-opus-*", "claude-sonnet-4-5"));
+        assert!(glob_match("*", "anything"));
+        assert_eq!(
+            glob_captures("claude-*-4-5", "claude-sonnet-4-5"),
+            Some(vec!["sonnet".to_string()])
+        );
+    }
+
+    #[test]
+    fn exact_rules_beat_globs() {
+        let map = model_map(&[
+            ("claude-*", "relay-default"),
+            ("claude-sonnet-4-5", "claude-sonnet-4-5-20250929"),
+        ]);
+
+        assert_eq!(
+            map.map("claude-sonnet-4-5").as_deref(),
+            Some("claude-sonnet-4-5-20250929")
+        );
+        assert_eq!(
+            map.map("claude-haiku-4-5").as_deref(),
+            Some("relay-default")
+        );
+        assert_eq!(map.map("gpt-5"), None);
+    }
+
+    #[test]
+    fn target_wildcard_substitutes_capture() {
+        let map = model_map(&[("claude-*", "anthropic/claude-*")]);
+        assert_eq!(
+            map.map("claude-opus-4-1").as_deref(),
+            Some("anthropic/claude-opus-4-1")
+        );
+    }
+
+    #[test]
+    fn model_filter_applies_allow_and_deny_lists() {
+        let open = ModelFilter::default();
+        assert!(open.allows("claude-opus-4-1"));
+
+        let cheap = ModelFilter {
+            allow: vec!["claude-haiku-*".into(), "claude-sonnet-*".into()],
+            deny: vec![],
+        };
+        assert!(cheap.allows("claude-sonnet-4-5"));
+        assert!(!cheap.allows("claude-opus-4-1"));
+
+        let no_opus = ModelFilter {
+            allow: vec![],
+            deny: vec!["claude-opus-*".into()],
+        };
+        assert!(no_opus.allows("claude-haiku-4-5"));
+        assert!(!no_opus.allows("claude-opus-4-1"));
+    }
+
+    #[test]
+    fn rewrites_request_and_json_response() {
+        let request = json!({ "model": "claude-sonnet-4-5", "max_tokens": 10 });
+        let body = rewrite_request_model(&request, "upstream-sonnet").unwrap();
+        let value: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(value["model"], "upstream-sonnet");
+        assert_eq!(value["max_tokens"], 10);
+
+        let restored = restore_json_model(
+            br#"{"model":"upstream-sonnet","id":"1"}"#,
+            "claude-sonnet-4-5",
+        )
+        .unwrap();
+        let value: Value = serde_json::from_slice(&restored).unwrap();
+        assert_eq!(value["model"], "claude-sonnet-4-5");
+    }
+
+    #[tokio::test]
+    async fn restores_model_in_sse_stream() {
+        let chunks = vec![
+            Ok::<_, std::io::Error>(Bytes::from(
+                "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"model\":\"up\"}}\n",
+            )),
+            Ok(Bytes::from(
+                "\nevent: content_block_delta\ndata: {\"type\":\"content_block_delta\"}\n\n",
+            )),
+        ];
+        let stream = restore_sse_model(futures::stream::iter(chunks), "claude-sonnet-4-5".into());
+        let out: Vec<Bytes> = stream.map(|c| c.unwrap()).collect().await;
+        let text: String = out
+            .iter()
+            .map(|b| String::from_utf8_lossy(b).to_string())
+            .collect();
+
+        assert!(text.contains("\"model\":\"claude-sonnet-4-5\""));
+        assert!(text.contains("event: content_block_delta\n"));
+    }
+
+    #[tokio::test]
+    async fn passes_other_sse_lines_through_unchanged() {
+        let untouched = ": keep-alive\n\nid: 7\nretry: 1000\nevent: ping\ndata: {}\n\n";
+        let chunks = vec![
+            Ok::<_, std::io::Error>(Bytes::from(untouched)),
+            Ok(Bytes::from(
+                "data: {\"model\":\"up\"}\n\ndata: {\"partial\"",
+            )),
+        ];
+        let stream = restore_sse_model(futures::stream::iter(chunks), "claude".into());
+        let out: Vec<Bytes> = stream.map(|c| c.unwrap()).collect().await;
+        let text: String = out
+            .iter()
+            .map(|b| String::from_utf8_lossy(b).to_string())
+            .collect();
+
+        assert_eq!(
+            text,
+            format!(
+                "{}data: {{\"model\":\"claude\"}}\n\ndata: {{\"partial\"",
+                untouched
+            )
+        );
+    }
+
+    #[test]
+    fn glob_with_many_stars_is_not_exponential() {
+        let text = "a".repeat(10_000);
+        assert!(!glob_match("*a*a*a*a*a*a*a*a*b", &text));
+        assert_eq!(
+            glob_captures("*-*-*", "a-b-c-d"),
+            Some(vec!["a".to_string(), "b".to_string(), "c-d".to_string()])
+        );
+        assert_eq!(glob_captures("a*", "a"), Some(vec![String::new()]));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::model_rules::glob_match;
+use crate::usage::Usage;
+use serde::{Deserialize, Serialize};
+
+/// One entry of the `pricing` table in provider.json. Prices are USD per
+/// million tokens; `model` may be a glob, `provider` narrows the rule to one
+/// provider `name`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PriceRule {
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub input: f64,
+    #[serde(default)]
+    pub output: f64,
+    #[serde(rename = "cacheWrite", default)]
+    pub cache_write: f64,
+    /// Price of 1h cache writes; defaults to `cacheWrite`
+    #[serde(rename = "cacheWrite1h", skip_serializing_if = "Option::is_none")]
+    pub cache_write_1h: Option<f64>,
+    #[serde(rename = "cacheRead", default)]
+    pub cache_read: f64,
+}
+
+/// Estimated spend of one response, in USD
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Cost {
+    pub total: f64,
+    /// What the cache reads would have cost as regular input, minus what they did cost
+    pub cache_savings: f64,
+}
+
+/// Most specific rule for a model: provider-specific rules first, then exact
+/// model names, then longer patterns
+fn find_rule<'a>(
+    rules: &'a [PriceRule],
+    provider: Option<&str>,
+    model: &str,
+) -> Option<&'a PriceRule> {
+    rules
+        .iter()
+        .filter(|rule| match &rule.provider {
+            Some(name) => Some(name.as_str()) == provider,
+            None => true,
+        })
+        .filter(|rule| glob_match(&rule.model, model))
+        .max_by_key(|rule| {
+            (
+                rule.provider.is_some(),
+                !rule.model.contains(['*', '?']),
+                rule.model.len(),
+            )
+        })
+}
+
+/// Price a response with the configured table; `None` when no rule matches
+pub fn estimate_cost(
+    rules: &[PriceRule],
+    provider: Option<&str>,
+    model: &str,
+    usage: &Usage,
+) -> Option<Cost> {
+    let rule = find_rule(rules, provider, model)?;
+    let per_token = |price: f64| price / 1_000_000.0;
+
+    let write_1h = usage.cache_write_1h_tokens.min(usage.cache_write_tokens);
+    let write_default = usage.cache_write_tokens - write_1h;
+    let total = usage.input_tokens as f64 * per_token(rule.input)
+        + usage.output_tokens as f64 * per_token(rule.output)
+        + write_default as f64 * per_token(rule.cache_write)
+        + write_1h as f64 * per_token(rule.cache_write_1h.unwrap_or(rule.cache_write))
+        + usage.cache_read_tokens as f64 * per_token(rule.cache_read);
+    let cache_savings = usage.cache_read_tokens as f64 * per_token(rule.input - rule.cache_read);
+
+    Some(Cost {
+        total,
+        cache_savings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(model: &str, provider: Option<&str>, input: f64) -> PriceRule {
+        PriceRule {
+            model: model.into(),
+            provider: provider.map(str::to_string),
+            input,
+            output: input * 5.0,
+            cache_write: input * 1.25,
+            cache_write_1h: Some(input * 2.0),
+            cache_read: input * 0.1,
+        }
+    }
+
+    #[test]
+    fn provider_and_exact_rules_win() {
+        let rules = vec![
+            rule("claude-*", None, 1.0),
+            rule("claude-sonnet-4-5", None, 3.0),
+            rule("claude-*", Some("cheap-relay"), 0.5),
+        ];
+
+        let pick = |provider, model| find_rule(&rules, provider, model).unwrap().input;
+        assert_eq!(pick(None, "claude-sonnet-4-5"), 3.0);
+        assert_eq!(pick(None, "claude-haiku-4-5"), 1.0);
+        assert_eq!(pick(Some("cheap-relay"), "claude-sonnet-4-5"), 0.5);
+        assert!(find_rule(&rules, None, "gpt-5").is_none());
+    }
+
+    #[test]
+    fn prices_every_token_class() {
+        let rules = vec![rule("claude-sonnet-4-5", None, 3.0)];
+        let usage = Usage {
+            input_tokens: 1_000_000,
+            output_tokens: 1_000_000,
+            cache_read_tokens: 1_000_000,
+            cache_write_tokens: 2_000_000,
+            cache_write_1h_tokens: 1_000_000,
+        };
+
+        let cost = estimate_cost(&rules, None, "claude-sonnet-4-5", &usage).unwrap();
+        // 3 input + 15 output + 3.75 5m write + 6 1h write + 0.3 read
+        assert!((cost.total - 28.05).abs() < 1e-9);
+        assert!((cost.cache_savings - 2.7).abs() < 1e-9);
+    }
+}
@@ -1,15 +1,72 @@
+use crate::timeouts::TimeoutConfig;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
+/// How the provider's API key is sent upstream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthScheme {
+    /// `Authorization: Bearer <key>`
+    #[default]
+    Bearer,
+    /// `x-api-key: <key>` plus `anthropic-version` (native Anthropic API)
+    XApiKey,
+    /// No credentials (e.g. a local server)
+    None,
+    /// `<authHeader>: <key>`
+    CustomHeader,
+}
+
+/// Wire protocol an upstream endpoint speaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Protocol {
+    /// `POST /v1/messages` (Claude Code's own API)
+    AnthropicMessages,
+    /// `POST /chat/completions` (the `openai` kind's own API)
+    OpenaiChat,
+    /// `POST /responses` (Codex's own API)
+    OpenaiResponses,
+}
+
+impl Protocol {
+    /// The protocol clients of `kind` speak, used when `protocol` is unset
+    pub fn native(kind: &str) -> Self {
+        match kind {
+            "claude" => Protocol::AnthropicMessages,
+            "openai" => Protocol::OpenaiChat,
+            _ => Protocol::OpenaiResponses,
+        }
+    }
+
+    /// Path appended to `apiUrl`
+    pub fn endpoint(self) -> &'static str {
+        match self {
+            Protocol::AnthropicMessages => "/v1/messages",
+            Protocol::OpenaiChat => "/chat/completions",
+            Protocol::OpenaiResponses => "/responses",
+        }
+    }
+}
+
 /// Platform-specific configuration (apiUrl + apiKey)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PlatformConfig {
     #[serde(rename = "apiUrl")]
     pub api_url: String,
-    #[serde(rename = "apiKey")]
+    #[serde(rename = "apiKey", default)]
     pub api_key: String,
+    #[serde(rename = "authScheme", default)]
+    pub auth_scheme: AuthScheme,
+    /// Header name used by the `custom-header` scheme
+    #[serde(rename = "authHeader", default)]
+    pub auth_header: Option<String>,
+    /// What the endpoint speaks; defaults to the client's own protocol
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<Protocol>,
 }
 
 /// Provider with platform-specific configs
@@ -17,8 +74,12 @@ pub struct PlatformConfig {
 pub struct Provider {
     #[serde(default = "default_enabled")]
     pub enabled: bool,
+    /// Priority tier; lower levels are tried first
     #[serde(default)]
     pub level: i32,
+    /// Relative share of traffic within a tier under the `weighted` strategy
+    #[serde(default = "default_weight")]
+    pub weight: u32,
     pub name: Option<String>,
     #[serde(rename = "apiUrl")]
     pub api_url: Option<String>,
@@ -26,11 +87,42 @@ pub struct Provider {
     pub api_key: Option<String>,
     pub codex: Option<PlatformConfig>,
     pub claude: Option<PlatformConfig>,
+    /// Generic OpenAI-compatible clients (`/v1/chat/completions`, `/v1/embeddings`, ...)
+    pub openai: Option<PlatformConfig>,
+    /// Error statuses that always fail over to the next provider
+    #[serde(rename = "retryableStatuses", default)]
+    pub retryable_statuses: Vec<u16>,
+    /// Error statuses that are always returned to the client unchanged
+    #[serde(rename = "nonRetryableStatuses", default)]
+    pub non_retryable_statuses: Vec<u16>,
+    /// Overrides for the global timeouts
+    #[serde(flatten)]
+    pub timeouts: TimeoutConfig,
+    /// Models this provider serves (exact or glob); empty means all
+    #[serde(default)]
+    pub models: Vec<String>,
+    /// Models this provider never serves (exact or glob)
+    #[serde(rename = "excludeModels", default)]
+    pub exclude_models: Vec<String>,
+    /// Requested model (exact or glob) → upstream model name
+    #[serde(rename = "modelMap", default)]
+    pub model_map: HashMap<String, String>,
+    /// Map the upstream model name in responses back to the requested one
+    #[serde(rename = "mapResponseModel", default)]
+    pub map_response_model: bool,
+    /// Other client kinds this provider's endpoints also serve, once their
+    /// own providers are exhausted (e.g. `["codex"]` on a `claude` endpoint)
+    #[serde(rename = "servesKinds", default)]
+    pub serves_kinds: Vec<String>,
 }
 
 impl Provider {
     /// Get platform-specific config for this provider
     pub fn get_platform_config(&self, kind: &str) -> Option<PlatformConfig> {
+        if kind == "openai" {
+            return self.openai.clone();
+        }
+
         let platform_config = match kind {
             "codex" => self.codex.clone(),
             "claude" => self.claude.clone(),
@@ -41,21 +133,50 @@ impl Provider {
             return platform_config;
         }
 
-        // Backward-compatible fallback to a single shared config
+        // Backward-compatible fallback to a single shared config (predates the `openai` kind)
         match (&self.api_url, &self.api_key) {
             (Some(url), Some(key)) if !url.is_empty() && !key.is_empty() => Some(PlatformConfig {
                 api_url: url.clone(),
                 api_key: key.clone(),
+                ..Default::default()
             }),
             _ => None,
         }
     }
 }
 
+impl Default for Provider {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            level: 0,
+            weight: default_weight(),
+            name: None,
+            api_url: None,
+            api_key: None,
+            codex: None,
+            claude: None,
+            openai: None,
+            retryable_statuses: Vec::new(),
+            non_retryable_statuses: Vec::new(),
+            timeouts: TimeoutConfig::default(),
+            models: Vec::new(),
+            exclude_models: Vec::new(),
+            model_map: HashMap::new(),
+            map_response_model: false,
+            serves_kinds: Vec::new(),
+        }
+    }
+}
+
 fn default_enabled() -> bool {
     true
 }
 
+fn default_weight() -> u32 {
+    1
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum PlatformConfigList {
@@ -76,13 +197,14 @@ impl PlatformConfigList {
 pub struct ProviderMapConfig {
     pub codex: Option<PlatformConfigList>,
     pub claude: Option<PlatformConfigList>,
+    pub openai: Option<PlatformConfigList>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum ProviderConfig {
     List { providers: Vec<Provider> },
-    Map { providers: ProviderMapConfig },
+    Map { providers: Box<ProviderMapConfig> },
 }
 
 /// Load providers from configuration file
@@ -108,13 +230,8 @@ pub fn load_providers() -> Result<Vec<Provider>> {
             if let Some(codex_list) = providers.codex {
                 for cfg in codex_list.into_vec() {
                     flattened.push(Provider {
-                        enabled: default_enabled(),
-                        level: 0,
-                        name: None,
-                        api_url: None,
-                        api_key: None,
                         codex: Some(cfg),
-                        claude: None,
+                        ..Default::default()
                     });
                 }
             }
@@ -122,13 +239,17 @@ pub fn load_providers() -> Result<Vec<Provider>> {
             if let Some(claude_list) = providers.claude {
                 for cfg in claude_list.into_vec() {
                     flattened.push(Provider {
-                        enabled: default_enabled(),
-                        level: 0,
-                        name: None,
-                        api_url: None,
-                        api_key: None,
-                        codex: None,
                         claude: Some(cfg),
+                        ..Default::default()
+                    });
+                }
+            }
+
+            if let Some(openai_list) = providers.openai {
+                for cfg in openai_list.into_vec() {
+                    flattened.push(Provider {
+                        openai: Some(cfg),
+                        ..Default::default()
                     });
                 }
             }
@@ -169,10 +290,7 @@ mod tests {
             enabled: default_enabled(),
             level: 0,
             name: Some("test".to_string()),
-            api_url: None,
-            api_key: None,
-            codex: None,
-            claude: None,
+            ..Default::default()
         };
 
         assert!(provider.enabled);
@@ -184,16 +302,17 @@ mod tests {
             enabled: true,
             level: 1,
             name: Some("test".to_string()),
-            api_url: None,
-            api_key: None,
             codex: Some(PlatformConfig {
                 api_url: "https://codex.api.com".to_string(),
                 api_key: "codex-key".to_string(),
+                ..Default::default()
             }),
             claude: Some(PlatformConfig {
                 api_url: "https://claude.api.com".to_string(),
                 api_key: "claude-key".to_string(),
+                ..Default::default()
             }),
+            ..Default::default()
         };
 
         let codex_config = provider.get_platform_config("codex").unwrap();
@@ -226,13 +345,8 @@ mod tests {
                 if let Some(codex) = providers.codex {
                     for cfg in codex.into_vec() {
                         flattened.push(Provider {
-                            enabled: default_enabled(),
-                            level: 0,
-                            name: None,
-                            api_url: None,
-                            api_key: None,
                             codex: Some(cfg),
-                            claude: None,
+                            ..Default::default()
                         });
                     }
                 }
@@ -240,13 +354,8 @@ mod tests {
                 if let Some(claude) = providers.claude {
                     for cfg in claude.into_vec() {
                         flattened.push(Provider {
-                            enabled: default_enabled(),
-                            level: 0,
-                            name: None,
-                            api_url: None,
-                            api_key: None,
-                            codex: None,
                             claude: Some(cfg),
+                            ..Default::default()
                         });
                     }
                 }
@@ -269,6 +378,84 @@ mod tests {
         );
     }
 
+    #[test]
+    fn list_config_parses_provider_fields() {
+        let json = r#"
+        {
+            "providers": [
+                {
+                    "name": "primary",
+                    "level": 0,
+                    "weight": 3,
+                    "apiUrl": "https://a.api",
+                    "apiKey": "k1",
+                    "retryableStatuses": [400],
+                    "nonRetryableStatuses": [429],
+                    "firstByteTimeoutMs": 30000,
+                    "models": ["claude-haiku-*", "claude-sonnet-*"],
+                    "excludeModels": ["claude-opus-*"],
+                    "modelMap": { "claude-sonnet-4-5": "claude-sonnet-4-5-20250929" },
+                    "mapResponseModel": true
+                },
+                { "name": "backup", "level": 1, "apiUrl": "https://b.api", "apiKey": "k2" }
+            ]
+        }
+        "#;
+
+        let config: ProviderConfig = serde_json::from_str(json).unwrap();
+        let ProviderConfig::List { providers } = config else {
+            panic!("expected list config");
+        };
+
+        let primary = &providers[0];
+        assert_eq!(primary.weight, 3);
+        assert_eq!(primary.retryable_statuses, vec![400]);
+        assert_eq!(primary.non_retryable_statuses, vec![429]);
+        assert_eq!(primary.timeouts.first_byte_timeout_ms, Some(30000));
+        assert_eq!(primary.timeouts.connect_timeout_ms, None);
+        assert_eq!(primary.models.len(), 2);
+        assert_eq!(primary.exclude_models, vec!["claude-opus-*"]);
+        assert_eq!(
+            primary.model_map["claude-sonnet-4-5"],
+            "claude-sonnet-4-5-20250929"
+        );
+        assert!(primary.map_response_model);
+
+        let backup = &providers[1];
+        assert_eq!(backup.level, 1);
+        assert_eq!(backup.weight, 1);
+        assert!(backup.retryable_statuses.is_empty());
+        assert!(backup.models.is_empty());
+        assert!(!backup.map_response_model);
+    }
+
+    #[test]
+    fn map_config_parses_auth_scheme() {
+        let json = r#"
+        {
+            "providers": {
+                "claude": [
+                    { "apiUrl": "https://api.anthropic.com", "apiKey": "akey", "authScheme": "x-api-key" },
+                    { "apiUrl": "https://azure.api", "apiKey": "zkey", "authScheme": "custom-header", "authHeader": "api-key" },
+                    { "apiUrl": "http://localhost:8080", "authScheme": "none" }
+                ]
+            }
+        }
+        "#;
+
+        let config: ProviderConfig = serde_json::from_str(json).unwrap();
+        let ProviderConfig::Map { providers } = config else {
+            panic!("expected map config");
+        };
+        let claude = providers.claude.unwrap().into_vec();
+
+        assert_eq!(claude[0].auth_scheme, AuthScheme::XApiKey);
+        assert_eq!(claude[1].auth_scheme, AuthScheme::CustomHeader);
+        assert_eq!(claude[1].auth_header.as_deref(), Some("api-key"));
+        assert_eq!(claude[2].auth_scheme, AuthScheme::None);
+        assert!(claude[2].api_key.is_empty());
+    }
+
     #[test]
     fn get_platform_config_falls_back_to_shared_keys() {
         let provider = Provider {
@@ -277,8 +464,7 @@ mod tests {
             name: None,
             api_url: Some("https://shared.api.com".to_string()),
             api_key: Some("shared-key".to_string()),
-            codex: None,
-            claude: None,
+            ..Default::default()
         };
 
         let codex_config = provider.get_platform_config("codex").unwrap();
@@ -286,5 +472,7 @@ mod tests {
 
         let claude_config = provider.get_platform_config("claude").unwrap();
         assert_eq!(claude_config.api_url, "https://shared.api.com");
+
+        assert!(provider.get_platform_config("openai").is_none());
     }
 }
@@ -0,0 +1,244 @@
+use axum::body::Body;
+use axum::http::Response;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Per-client limits, set alongside `name` and `token` in `clients`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClientLimits {
+    /// Requests per minute (sliding window)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rpm: Option<u32>,
+    /// Streamed requests (`"stream": true`) whose response is still streaming
+    #[serde(
+        rename = "maxConcurrentStreams",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_concurrent_streams: Option<u32>,
+    /// Input, output and cache-write tokens per UTC day (cache reads are not counted)
+    #[serde(rename = "dailyTokens", skip_serializing_if = "Option::is_none")]
+    pub daily_tokens: Option<u64>,
+    /// Estimated spend per UTC day, from the `pricing` table
+    #[serde(rename = "dailyCostUsd", skip_serializing_if = "Option::is_none")]
+    pub daily_cost_usd: Option<f64>,
+}
+
+/// Why a request was refused and when the client may try again
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaExceeded {
+    pub message: String,
+    pub retry_after_secs: u64,
+}
+
+/// Request rates and open streams per client name
+#[derive(Default)]
+pub struct QuotaTracker {
+    recent: Mutex<HashMap<String, VecDeque<Instant>>>,
+    streams: Mutex<HashMap<String, u32>>,
+}
+
+impl QuotaTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Admit one request, given what the client has used today. The permit
+    /// of a `streaming` request counts as an open stream until dropped.
+    pub fn acquire(
+        self: &Arc<Self>,
+        client: &str,
+        limits: &ClientLimits,
+        tokens_today: u64,
+        cost_today: f64,
+        streaming: bool,
+    ) -> Result<QuotaPermit, QuotaExceeded> {
+        if let Some(budget) = limits.daily_tokens.filter(|b| tokens_today >= *b) {
+            return Err(QuotaExceeded {
+                message: format!("Daily token budget of {} exhausted", budget),
+                retry_after_secs: secs_until_utc_midnight(),
+            });
+        }
+        if let Some(budget) = limits.daily_cost_usd.filter(|b| cost_today >= *b) {
+            return Err(QuotaExceeded {
+                message: format!("Daily budget of ${:.2} exhausted", budget),
+                retry_after_secs: secs_until_utc_midnight(),
+            });
+        }
+
+        // Both locks are held so a rejected request leaves no trace
+        let mut streams = self.streams.lock().unwrap();
+        let open = streams.get(client).copied().unwrap_or(0);
+        let stream_limit = limits.max_concurrent_streams.filter(|_| streaming);
+        if let Some(max) = stream_limit.filter(|max| open >= *max) {
+            return Err(QuotaExceeded {
+                message: format!("Concurrent stream limit of {} reached", max),
+                retry_after_secs: 1,
+            });
+        }
+
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        let window = recent.entry(client.to_string()).or_default();
+        while window
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= RATE_WINDOW)
+        {
+            window.pop_front();
+        }
+        if let Some(rpm) = limits.rpm.filter(|rpm| window.len() >= *rpm as usize) {
+            let oldest = window.front().copied().unwrap_or(now);
+            let wait = RATE_WINDOW.saturating_sub(now.duration_since(oldest));
+            return Err(QuotaExceeded {
+                message: format!("Rate limit of {} requests per minute exceeded", rpm),
+                retry_after_secs: wait.as_secs_f64().ceil().max(1.0) as u64,
+            });
+        }
+        window.push_back(now);
+
+        if streaming {
+            *streams.entry(client.to_string()).or_default() += 1;
+        }
+        Ok(QuotaPermit {
+            tracker: self.clone(),
+            client: client.to_string(),
+            holds_stream: streaming,
+        })
+    }
+}
+
+/// An admitted request; releases its stream slot (if any) when dropped
+pub struct QuotaPermit {
+    tracker: Arc<QuotaTracker>,
+    client: String,
+    holds_stream: bool,
+}
+
+impl QuotaPermit {
+    /// Keep the stream slot until the client stops reading the response.
+    /// A response that turned out not to stream gives it back right away.
+    pub fn hold_until_done(self, response: Response<Body>) -> Response<Body> {
+        let is_event_stream = response
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| ct.contains("text/event-stream"));
+        if !self.holds_stream || !is_event_stream {
+            return response;
+        }
+
+        let (parts, body) = response.into_parts();
+        let stream = body.into_data_stream().map(move |chunk| {
+            let _ = &self;
+            chunk
+        });
+        Response::from_parts(parts, Body::from_stream(stream))
+    }
+}
+
+impl Drop for QuotaPermit {
+    fn drop(&mut self) {
+        if !self.holds_stream {
+            return;
+        }
+        if let Some(open) = self.tracker.streams.lock().unwrap().get_mut(&self.client) {
+            *open = open.saturating_sub(1);
+        }
+    }
+}
+
+fn secs_until_utc_midnight() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    86_400 - now % 86_400
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_requests_per_minute() {
+        let tracker = Arc::new(QuotaTracker::new());
+        let limits = ClientLimits {
+            rpm: Some(2),
+            ..Default::default()
+        };
+
+        assert!(tracker.acquire("ci", &limits, 0, 0.0, true).is_ok());
+        assert!(tracker.acquire("ci", &limits, 0, 0.0, true).is_ok());
+        let err = tracker.acquire("ci", &limits, 0, 0.0, true).err().unwrap();
+        assert!((1..=60).contains(&err.retry_after_secs));
+        // Other clients have their own window
+        assert!(tracker.acquire("laptop", &limits, 0, 0.0, true).is_ok());
+    }
+
+    #[test]
+    fn releases_stream_slot_on_drop() {
+        let tracker = Arc::new(QuotaTracker::new());
+        let limits = ClientLimits {
+            max_concurrent_streams: Some(1),
+            ..Default::default()
+        };
+
+        let permit = tracker.acquire("ci", &limits, 0, 0.0, true).unwrap();
+        assert!(tracker.acquire("ci", &limits, 0, 0.0, true).is_err());
+        drop(permit);
+        assert!(tracker.acquire("ci", &limits, 0, 0.0, true).is_ok());
+    }
+
+    #[test]
+    fn non_streaming_requests_take_no_stream_slot() {
+        let tracker = Arc::new(QuotaTracker::new());
+        let limits = ClientLimits {
+            max_concurrent_streams: Some(1),
+            ..Default::default()
+        };
+
+        let _stream = tracker.acquire("ci", &limits, 0, 0.0, true).unwrap();
+        assert!(tracker.acquire("ci", &limits, 0, 0.0, true).is_err());
+        let _count_tokens = tracker.acquire("ci", &limits, 0, 0.0, false).unwrap();
+        assert!(tracker.acquire("ci", &limits, 0, 0.0, false).is_ok());
+    }
+
+    #[test]
+    fn buffered_response_releases_stream_slot() {
+        let tracker = Arc::new(QuotaTracker::new());
+        let limits = ClientLimits {
+            max_concurrent_streams: Some(1),
+            ..Default::default()
+        };
+
+        let permit = tracker.acquire("ci", &limits, 0, 0.0, true).unwrap();
+        let response = Response::builder()
+            .header("content-type", "application/json")
+            .body(Body::from("{}"))
+            .unwrap();
+        let _response = permit.hold_until_done(response);
+        assert!(tracker.acquire("ci", &limits, 0, 0.0, true).is_ok());
+    }
+
+    #[test]
+    fn daily_budgets_wait_for_midnight() {
+        let tracker = Arc::new(QuotaTracker::new());
+        let limits = ClientLimits {
+            daily_tokens: Some(1_000),
+            daily_cost_usd: Some(5.0),
+            ..Default::default()
+        };
+
+        assert!(tracker.acquire("ci", &limits, 999, 4.99, true).is_ok());
+        let err = tracker
+            .acquire("ci", &limits, 1_000, 0.0, true)
+            .err()
+            .unwrap();
+        assert!(err.retry_after_secs <= 86_400);
+        assert!(tracker.acquire("ci", &limits, 0, 5.0, true).is_err());
+    }
+}
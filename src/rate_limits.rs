@@ -0,0 +1,408 @@
+use axum::http::{HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Share of a request or token limit below which a provider is tried last
+const LOW_QUOTA_FRACTION: f64 = 0.05;
+
+/// How long a reading without a usable reset time is trusted
+const DEFAULT_VALIDITY: Duration = Duration::from_secs(60);
+
+/// Longest wait taken from a header; later resets and retry times are capped
+const MAX_WAIT: Duration = Duration::from_secs(24 * 3600);
+
+/// Resources reported by Anthropic (`anthropic-ratelimit-<resource>-*`)
+const ANTHROPIC_RESOURCES: &[&str] = &["requests", "tokens", "input-tokens", "output-tokens"];
+
+/// Resources reported by OpenAI (`x-ratelimit-*-<resource>`)
+const OPENAI_RESOURCES: &[&str] = &["requests", "tokens"];
+
+#[derive(Debug, Clone, Copy)]
+struct Quota {
+    limit: Option<u64>,
+    remaining: u64,
+    reset_at: Instant,
+}
+
+impl Quota {
+    fn is_current(&self, now: Instant) -> bool {
+        now < self.reset_at
+    }
+
+    fn is_low(&self) -> bool {
+        match self.limit.filter(|limit| *limit > 0) {
+            Some(limit) => (self.remaining as f64) < limit as f64 * LOW_QUOTA_FRACTION,
+            None => self.remaining == 0,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ProviderLimits {
+    quotas: HashMap<&'static str, Quota>,
+    cooldown_until: Option<Instant>,
+}
+
+/// Upstream quota of one provider as last reported, for the admin API
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RateLimitSnapshot {
+    /// Remaining requests / tokens per resource until the next reset
+    pub remaining: BTreeMap<String, u64>,
+    /// Whether the provider is tried after the others
+    pub low: bool,
+    /// Time left of a `retry-after` cooldown
+    #[serde(rename = "cooldownSecs", skip_serializing_if = "Option::is_none")]
+    pub cooldown_secs: Option<u64>,
+}
+
+/// Per-provider quota read from upstream rate-limit response headers
+#[derive(Default)]
+pub struct RateLimitTracker {
+    entries: Mutex<HashMap<String, ProviderLimits>>,
+}
+
+impl RateLimitTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the rate-limit headers of an upstream response. A 429 with
+    /// `retry-after` cools the provider down for exactly that long.
+    pub fn observe(&self, provider_id: &str, status: StatusCode, headers: &HeaderMap) {
+        let now = Instant::now();
+        let quotas = parse_quotas(headers, now);
+        let cooldown = (status == StatusCode::TOO_MANY_REQUESTS)
+            .then(|| retry_after(headers))
+            .flatten();
+        if quotas.is_empty() && cooldown.is_none() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(provider_id.to_string()).or_default();
+        entry.quotas.extend(quotas);
+        if let Some(wait) = cooldown {
+            tracing::warn!(
+                "Provider rate limited, cooling down for {}s: {}",
+                wait.as_secs(),
+                provider_id
+            );
+            entry.cooldown_until = now.checked_add(wait);
+        }
+    }
+
+    /// Time left before a rate-limited provider may be tried again
+    pub fn cooldown_remaining(&self, provider_id: &str) -> Option<Duration> {
+        let entries = self.entries.lock().unwrap();
+        let until = entries.get(provider_id)?.cooldown_until?;
+        let remaining = until.saturating_duration_since(Instant::now());
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    /// Whether any current quota of the provider is nearly used up
+    pub fn is_low(&self, provider_id: &str) -> bool {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        entries.get(provider_id).is_some_and(|entry| {
+            entry
+                .quotas
+                .values()
+                .any(|quota| quota.is_current(now) && quota.is_low())
+        })
+    }
+
+    pub fn snapshot(&self, provider_id: &str) -> Option<RateLimitSnapshot> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(provider_id)?;
+
+        let current: Vec<(&&str, &Quota)> = entry
+            .quotas
+            .iter()
+            .filter(|(_, quota)| quota.is_current(now))
+            .collect();
+        let cooldown_secs = entry
+            .cooldown_until
+            .map(|until| until.saturating_duration_since(now))
+            .filter(|wait| !wait.is_zero())
+            .map(|wait| wait.as_secs_f64().ceil() as u64);
+        if current.is_empty() && cooldown_secs.is_none() {
+            return None;
+        }
+
+        Some(RateLimitSnapshot {
+            remaining: current
+                .iter()
+                .map(|(resource, quota)| (resource.to_string(), quota.remaining))
+                .collect(),
+            low: current.iter().any(|(_, quota)| quota.is_low()),
+            cooldown_secs,
+        })
+    }
+}
+
+fn parse_quotas(headers: &HeaderMap, now: Instant) -> Vec<(&'static str, Quota)> {
+    let header = |name: String| headers.get(name).and_then(|v| v.to_str().ok());
+
+    let anthropic = ANTHROPIC_RESOURCES.iter().map(|resource| {
+        (
+            *resource,
+            header(format!("anthropic-ratelimit-{}-limit", resource)),
+            header(format!("anthropic-ratelimit-{}-remaining", resource)),
+            header(format!("anthropic-ratelimit-{}-reset", resource)),
+        )
+    });
+    let openai = OPENAI_RESOURCES.iter().map(|resource| {
+        (
+            *resource,
+            header(format!("x-ratelimit-limit-{}", resource)),
+            header(format!("x-ratelimit-remaining-{}", resource)),
+            header(format!("x-ratelimit-reset-{}", resource)),
+        )
+    });
+
+    anthropic
+        .chain(openai)
+        .filter_map(|(resource, limit, remaining, reset)| {
+            let remaining = remaining?.trim().parse().ok()?;
+            let reset_in = reset.and_then(parse_reset).unwrap_or(DEFAULT_VALIDITY);
+            Some((
+                resource,
+                Quota {
+                    limit: limit.and_then(|v| v.trim().parse().ok()),
+                    remaining,
+                    reset_at: now.checked_add(reset_in)?,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// `retry-after` as delay-seconds or an HTTP date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get("retry-after")?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<f64>() {
+        return capped_secs(secs);
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(until(at))
+}
+
+/// Time until a reset given as an RFC 3339 timestamp (Anthropic),
+/// a duration like `6m0s` / `20ms` (OpenAI) or plain seconds
+fn parse_reset(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Some(at) = parse_rfc3339(value) {
+        return Some(until(at));
+    }
+    if let Ok(secs) = value.parse::<f64>() {
+        return capped_secs(secs);
+    }
+    parse_go_duration(value)
+}
+
+/// A wait in seconds from a header, capped at `MAX_WAIT`
+fn capped_secs(secs: f64) -> Option<Duration> {
+    if !secs.is_finite() || secs < 0.0 {
+        return None;
+    }
+    Some(
+        Duration::try_from_secs_f64(secs)
+            .unwrap_or(MAX_WAIT)
+            .min(MAX_WAIT),
+    )
+}
+
+/// Time from now until `at`, capped at `MAX_WAIT`
+fn until(at: SystemTime) -> Duration {
+    at.duration_since(SystemTime::now())
+        .unwrap_or_default()
+        .min(MAX_WAIT)
+}
+
+/// `1h2m3.5s`, `6m0s`, `20ms`
+fn parse_go_duration(value: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .filter(|len| *len > 0)?;
+        let number: f64 = rest[..number_len].parse().ok()?;
+        rest = &rest[number_len..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_len] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        total += number * scale;
+        rest = &rest[unit_len..];
+    }
+    capped_secs(total)
+}
+
+/// `2026-10-17T01:02:03Z` or with fractional seconds / a numeric offset
+fn parse_rfc3339(value: &str) -> Option<SystemTime> {
+    let (date, time) = value.split_once(['T', 't', ' '])?;
+    let mut date_parts = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (
+        date_parts.next()??,
+        date_parts.next()??,
+        date_parts.next()??,
+    );
+
+    let (clock, offset_secs) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+        (clock, 0)
+    } else {
+        let split = time.rfind(['+', '-'])?;
+        let (clock, offset) = time.split_at(split);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = offset[1..].split_once(':')?;
+        (
+            clock,
+            sign * (hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60),
+        )
+    };
+    let mut clock_parts = clock.splitn(3, ':');
+    let hour: i64 = clock_parts.next()?.parse().ok()?;
+    let minute: i64 = clock_parts.next()?.parse().ok()?;
+    let second: f64 = clock_parts.next()?.parse().ok()?;
+
+    // Keeps the arithmetic below far from overflowing
+    if !(1970..=9999).contains(&year) {
+        return None;
+    }
+
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 - offset_secs;
+    let secs = secs as f64 + second;
+    if secs < 0.0 {
+        return None;
+    }
+    UNIX_EPOCH.checked_add(Duration::try_from_secs_f64(secs).ok()?)
+}
+
+/// Days since the Unix epoch of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Howard Hinnant's days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn parses_reset_formats() {
+        assert_eq!(parse_go_duration("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(parse_go_duration("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(
+            parse_go_duration("1h2m3.5s"),
+            Some(Duration::from_secs_f64(3723.5))
+        );
+        assert_eq!(parse_go_duration("soon"), None);
+
+        let epoch = |value| {
+            parse_rfc3339(value)
+                .unwrap()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+        };
+        assert_eq!(epoch("1970-01-02T00:00:00Z"), Duration::from_secs(86_400));
+        assert_eq!(epoch("2024-02-29T12:00:00Z").as_secs(), 1_709_208_000);
+        assert_eq!(epoch("2024-02-29T14:00:00+02:00").as_secs(), 1_709_208_000);
+    }
+
+    #[test]
+    fn tracks_low_quota_from_headers() {
+        let tracker = RateLimitTracker::new();
+        tracker.observe(
+            "anthropic",
+            StatusCode::OK,
+            &headers(&[
+                ("anthropic-ratelimit-requests-limit", "1000"),
+                ("anthropic-ratelimit-requests-remaining", "999"),
+                ("anthropic-ratelimit-tokens-limit", "100000"),
+                ("anthropic-ratelimit-tokens-remaining", "1200"),
+            ]),
+        );
+        assert!(tracker.is_low("anthropic"));
+        let snapshot = tracker.snapshot("anthropic").unwrap();
+        assert_eq!(snapshot.remaining["requests"], 999);
+
+        tracker.observe(
+            "openai",
+            StatusCode::OK,
+            &headers(&[
+                ("x-ratelimit-limit-requests", "500"),
+                ("x-ratelimit-remaining-requests", "499"),
+                ("x-ratelimit-reset-requests", "120ms"),
+            ]),
+        );
+        assert!(!tracker.is_low("openai"));
+        assert!(!tracker.is_low("unknown"));
+    }
+
+    #[test]
+    fn retry_after_on_429_cools_down() {
+        let tracker = RateLimitTracker::new();
+        tracker.observe(
+            "p1",
+            StatusCode::SERVICE_UNAVAILABLE,
+            &headers(&[("retry-after", "30")]),
+        );
+        assert!(tracker.cooldown_remaining("p1").is_none());
+
+        tracker.observe(
+            "p1",
+            StatusCode::TOO_MANY_REQUESTS,
+            &headers(&[("retry-after", "30")]),
+        );
+        let wait = tracker.cooldown_remaining("p1").unwrap();
+        assert!(wait > Duration::from_secs(29) && wait <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn huge_header_values_are_capped() {
+        assert_eq!(capped_secs(1e30), Some(MAX_WAIT));
+        assert_eq!(capped_secs(f64::MAX), Some(MAX_WAIT));
+        assert_eq!(capped_secs(f64::INFINITY), None);
+        assert_eq!(capped_secs(-1.0), None);
+        assert_eq!(parse_reset("99999999999999999999h"), Some(MAX_WAIT));
+        assert_eq!(parse_reset("9999-12-31T23:59:59Z"), Some(MAX_WAIT));
+        assert_eq!(parse_reset("99999999999999-01-01T00:00:00Z"), None);
+
+        let tracker = RateLimitTracker::new();
+        tracker.observe(
+            "p1",
+            StatusCode::TOO_MANY_REQUESTS,
+            &headers(&[
+                ("retry-after", "1e30"),
+                ("x-ratelimit-remaining-requests", "0"),
+                ("x-ratelimit-reset-requests", "1e300"),
+            ]),
+        );
+        assert!(tracker.cooldown_remaining("p1").unwrap() <= MAX_WAIT);
+        assert!(tracker.is_low("p1"));
+    }
+}
@@ -0,0 +1,525 @@
+//! OpenAI Responses ⇄ Chat Completions translation, for serving Codex from
+//! upstreams that only implement `/chat/completions`
+
+use crate::responses_events::{
+    function_call_item, item_id, message_item, reasoning_item, response_id, response_object,
+    ResponsesWriter,
+};
+use crate::sse::{self, SseParser};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde_json::{json, Map, Value};
+
+/// Translate a `/responses` request body into a `/chat/completions` one
+pub fn translate_request(request: &Value) -> Value {
+    let mut chat = Map::new();
+    chat.insert("model".into(), request["model"].clone());
+
+    let mut messages: Vec<Value> = Vec::new();
+    if let Some(instructions) = request["instructions"].as_str().filter(|s| !s.is_empty()) {
+        messages.push(json!({ "role": "system", "content": instructions }));
+    }
+    match &request["input"] {
+        Value::String(text) => messages.push(json!({ "role": "user", "content": text })),
+        Value::Array(items) => {
+            for item in items {
+                input_item(item, &mut messages);
+            }
+        }
+        _ => {}
+    }
+    chat.insert("messages".into(), messages.into());
+
+    for (from, to) in [
+        ("max_output_tokens", "max_tokens"),
+        ("temperature", "temperature"),
+        ("top_p", "top_p"),
+        ("parallel_tool_calls", "parallel_tool_calls"),
+    ] {
+        if !request[from].is_null() {
+            chat.insert(to.into(), request[from].clone());
+        }
+    }
+    if let Some(effort) = request["reasoning"]["effort"].as_str() {
+        chat.insert("reasoning_effort".into(), effort.into());
+    }
+    let format = &request["text"]["format"];
+    if format["type"] == "json_schema" {
+        chat.insert(
+            "response_format".into(),
+            json!({
+                "type": "json_schema",
+                "json_schema": {
+                    "name": format["name"],
+                    "schema": format["schema"],
+                    "strict": format["strict"],
+                },
+            }),
+        );
+    }
+
+    if request["stream"].as_bool() == Some(true) {
+        chat.insert("stream".into(), true.into());
+        // Usage only arrives in a final chunk when asked for
+        chat.insert("stream_options".into(), json!({ "include_usage": true }));
+    }
+
+    // Built-in tools (local_shell, web_search, custom grammars) have no Chat equivalent
+    let tools: Vec<Value> = request["tools"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|tool| tool["type"] == "function")
+        .map(|tool| {
+            let mut function = json!({
+                "name": tool["name"],
+                "parameters": tool["parameters"],
+            });
+            if let Some(description) = tool["description"].as_str() {
+                function["description"] = description.into();
+            }
+            json!({ "type": "function", "function": function })
+        })
+        .collect();
+    if !tools.is_empty() {
+        chat.insert("tools".into(), tools.into());
+        let choice = &request["tool_choice"];
+        if let Some(mode) = choice.as_str() {
+            chat.insert("tool_choice".into(), mode.into());
+        } else if choice["type"] == "function" {
+            chat.insert(
+                "tool_choice".into(),
+                json!({ "type": "function", "function": { "name": choice["name"] } }),
+            );
+        }
+    }
+
+    Value::Object(chat)
+}
+
+/// Append the Chat messages for one Responses input item; reasoning is dropped
+fn input_item(item: &Value, messages: &mut Vec<Value>) {
+    // Items without a type are plain `{role, content}` messages
+    match item["type"].as_str().unwrap_or("message") {
+        "message" => {
+            let role = match item["role"].as_str() {
+                Some("assistant") => "assistant",
+                Some("system") | Some("developer") => "system",
+                _ => "user",
+            };
+            messages.push(json!({ "role": role, "content": message_content(&item["content"]) }));
+        }
+        "function_call" => {
+            let call = json!({
+                "id": item["call_id"],
+                "type": "function",
+                "function": { "name": item["name"], "arguments": item["arguments"] },
+            });
+            // Parallel calls share one assistant message
+            match messages.last_mut() {
+                Some(last) if last["role"] == "assistant" => {
+                    if let Some(calls) = last["tool_calls"].as_array_mut() {
+                        calls.push(call);
+                    } else {
+                        last["tool_calls"] = json!([call]);
+                    }
+                }
+                _ => messages.push(json!({
+                    "role": "assistant",
+                    "content": Value::Null,
+                    "tool_calls": [call],
+                })),
+            }
+        }
+        "function_call_output" => messages.push(json!({
+            "role": "tool",
+            "tool_call_id": item["call_id"],
+            "content": text_of(&item["output"]),
+        })),
+        _ => {}
+    }
+}
+
+/// Chat content of a Responses message: a string unless it carries images
+fn message_content(content: &Value) -> Value {
+    let Some(parts) = content.as_array() else {
+        return content.clone();
+    };
+    if parts.iter().all(|part| part["text"].is_string()) {
+        return text_of(content).into();
+    }
+    parts
+        .iter()
+        .filter_map(|part| match part["type"].as_str()? {
+            "input_text" | "output_text" => Some(json!({ "type": "text", "text": part["text"] })),
+            "input_image" => Some(json!({
+                "type": "image_url",
+                "image_url": { "url": part["image_url"] },
+            })),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// Plain text of a string or an array of text parts
+fn text_of(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// Responses usage from a Chat Completions `usage` object
+fn usage_of(usage: &Value) -> Value {
+    let input = usage["prompt_tokens"].as_u64().unwrap_or(0);
+    let output = usage["completion_tokens"].as_u64().unwrap_or(0);
+    json!({
+        "input_tokens": input,
+        "input_tokens_details": {
+            "cached_tokens": usage["prompt_tokens_details"]["cached_tokens"].as_u64().unwrap_or(0),
+        },
+        "output_tokens": output,
+        "output_tokens_details": {
+            "reasoning_tokens": usage["completion_tokens_details"]["reasoning_tokens"].as_u64().unwrap_or(0),
+        },
+        "total_tokens": input + output,
+    })
+}
+
+/// Why a Responses output was cut short, from the Chat finish reason
+fn incomplete_reason(finish_reason: &str) -> Option<&'static str> {
+    match finish_reason {
+        "length" => Some("max_output_tokens"),
+        "content_filter" => Some("content_filter"),
+        _ => None,
+    }
+}
+
+/// Translate a buffered `/chat/completions` response into a Responses object
+pub fn translate_response(body: &[u8]) -> Option<Bytes> {
+    let chat: Value = serde_json::from_slice(body).ok()?;
+    let choice = chat["choices"].get(0)?;
+    let message = &choice["message"];
+
+    let mut output = Vec::new();
+    let reasoning = message["reasoning_content"]
+        .as_str()
+        .or_else(|| message["reasoning"].as_str());
+    if let Some(text) = reasoning.filter(|t| !t.is_empty()) {
+        output.push(reasoning_item(&item_id("rs"), text, None));
+    }
+    if let Some(text) = message["content"].as_str().filter(|t| !t.is_empty()) {
+        output.push(message_item(&item_id("msg"), text, "completed"));
+    }
+    for call in message["tool_calls"].as_array().into_iter().flatten() {
+        output.push(function_call_item(
+            &item_id("fc"),
+            &call["id"],
+            &call["function"]["name"],
+            call["function"]["arguments"].as_str().unwrap_or("{}"),
+        ));
+    }
+
+    let response = response_object(
+        &response_id(&chat["id"]),
+        &chat["model"],
+        output,
+        incomplete_reason(choice["finish_reason"].as_str().unwrap_or("stop")),
+        usage_of(&chat["usage"]),
+    );
+    serde_json::to_vec(&response).ok().map(Bytes::from)
+}
+
+/// Rebuilds the Responses event sequence from Chat Completions chunks
+#[derive(Default)]
+struct StreamTranslator {
+    parser: SseParser,
+    writer: ResponsesWriter,
+    finished: bool,
+    finish_reason: Option<String>,
+    usage: Value,
+}
+
+impl StreamTranslator {
+    fn feed(&mut self, chunk: &[u8]) -> Bytes {
+        let mut out = String::new();
+        for event in self.parser.feed(chunk) {
+            if self.finished {
+                continue;
+            }
+            if event.data.trim() == "[DONE]" {
+                self.close(&mut out);
+                continue;
+            }
+            if let Some(chunk) = event.json() {
+                self.chunk(&chunk, &mut out);
+            }
+        }
+        Bytes::from(out)
+    }
+
+    /// Called when the upstream stream ends
+    fn finish(&mut self) -> Bytes {
+        let mut out = String::new();
+        if !self.finished {
+            if self.finish_reason.is_some() {
+                self.close(&mut out);
+            } else {
+                self.finished = true;
+                out.push_str(&String::from_utf8_lossy(&sse::error_event(
+                    "codex",
+                    "server_error",
+                    "Upstream stream ended unexpectedly",
+                )));
+            }
+        }
+        Bytes::from(out)
+    }
+
+    fn chunk(&mut self, chunk: &Value, out: &mut String) {
+        if chunk["error"].is_object() {
+            let error = &chunk["error"];
+            let code = error["code"]
+                .as_str()
+                .or_else(|| error["type"].as_str())
+                .unwrap_or("server_error");
+            let message = error["message"].as_str().unwrap_or("Upstream error");
+            out.push_str(&String::from_utf8_lossy(&sse::error_event(
+                "codex", code, message,
+            )));
+            self.finished = true;
+            return;
+        }
+
+        self.writer
+            .start(response_id(&chunk["id"]), chunk["model"].clone(), out);
+
+        if chunk["usage"].is_object() {
+            self.usage = usage_of(&chunk["usage"]);
+        }
+
+        let Some(choice) = chunk["choices"].get(0) else {
+            return;
+        };
+        let delta = &choice["delta"];
+
+        let reasoning = delta["reasoning_content"]
+            .as_str()
+            .or_else(|| delta["reasoning"].as_str());
+        if let Some(text) = reasoning.filter(|t| !t.is_empty()) {
+            self.writer.reasoning_delta(text, out);
+        }
+
+        if let Some(text) = delta["content"].as_str().filter(|t| !t.is_empty()) {
+            self.writer.text_delta(text, out);
+        }
+
+        for call in delta["tool_calls"].as_array().into_iter().flatten() {
+            let index = call["index"].as_u64().unwrap_or(0);
+            self.writer
+                .open_call(index, &call["id"], &call["function"]["name"], out);
+            let arguments = call["function"]["arguments"].as_str().unwrap_or_default();
+            self.writer.call_arguments(arguments, out);
+        }
+
+        if let Some(reason) = choice["finish_reason"].as_str() {
+            self.finish_reason = Some(reason.to_string());
+        }
+    }
+
+    fn close(&mut self, out: &mut String) {
+        if !self.writer.is_started() {
+            self.chunk(&json!({}), out);
+        }
+        let usage = if self.usage.is_object() {
+            self.usage.clone()
+        } else {
+            usage_of(&Value::Null)
+        };
+        let incomplete = incomplete_reason(self.finish_reason.as_deref().unwrap_or("stop"));
+        self.writer.complete(incomplete, usage, out);
+        self.finished = true;
+    }
+}
+
+/// Translate a `/chat/completions` SSE stream into Responses SSE events
+pub fn translate_stream<S, E>(stream: S) -> impl Stream<Item = Result<Bytes, E>> + Send
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+    E: Send + 'static,
+{
+    futures::stream::unfold(
+        Some((stream, StreamTranslator::default())),
+        |state| async move {
+            let (mut stream, mut translator) = state?;
+            match stream.next().await {
+                Some(Ok(chunk)) => Some((Ok(translator.feed(&chunk)), Some((stream, translator)))),
+                Some(Err(e)) => Some((Err(e), Some((stream, translator)))),
+                None => Some((Ok(translator.finish()), None)),
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_request_with_calls_and_outputs() {
+        let request = json!({
+            "model": "qwen3-coder",
+            "instructions": "You are Codex.",
+            "stream": true,
+            "reasoning": { "effort": "high", "summary": "auto" },
+            "tools": [
+                { "type": "function", "name": "shell", "description": "Run a command", "parameters": { "type": "object" } },
+                { "type": "web_search" }
+            ],
+            "tool_choice": "auto",
+            "parallel_tool_calls": true,
+            "input": [
+                { "type": "message", "role": "developer", "content": [{ "type": "input_text", "text": "Sandbox: read-only" }] },
+                { "type": "message", "role": "user", "content": [
+                    { "type": "input_text", "text": "What is this?" },
+                    { "type": "input_image", "image_url": "data:image/png;base64,AAAA" }
+                ] },
+                { "type": "reasoning", "summary": [], "encrypted_content": "xyz" },
+                { "type": "message", "role": "assistant", "content": [{ "type": "output_text", "text": "Checking." }] },
+                { "type": "function_call", "call_id": "call_1", "name": "shell", "arguments": "{\"cmd\":\"ls\"}" },
+                { "type": "function_call", "call_id": "call_2", "name": "shell", "arguments": "{\"cmd\":\"pwd\"}" },
+                { "type": "function_call_output", "call_id": "call_1", "output": "a.txt" },
+                { "type": "function_call_output", "call_id": "call_2", "output": "/tmp" }
+            ]
+        });
+
+        let chat = translate_request(&request);
+        assert_eq!(chat["reasoning_effort"], "high");
+        assert_eq!(chat["tool_choice"], "auto");
+        assert_eq!(chat["stream_options"]["include_usage"], true);
+        assert_eq!(chat["tools"].as_array().unwrap().len(), 1);
+        assert_eq!(chat["tools"][0]["function"]["name"], "shell");
+
+        let messages = chat["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 6);
+        assert_eq!(messages[0]["content"], "You are Codex.");
+        assert_eq!(
+            messages[1],
+            json!({ "role": "system", "content": "Sandbox: read-only" })
+        );
+        assert_eq!(
+            messages[2]["content"][1]["image_url"]["url"],
+            "data:image/png;base64,AAAA"
+        );
+        assert_eq!(messages[3]["content"], "Checking.");
+        assert_eq!(messages[3]["tool_calls"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            messages[5],
+            json!({ "role": "tool", "tool_call_id": "call_2", "content": "/tmp" })
+        );
+    }
+
+    #[test]
+    fn translates_buffered_response() {
+        let body = json!({
+            "id": "chatcmpl-1",
+            "model": "qwen3-coder",
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": "Listing.",
+                    "tool_calls": [{ "id": "call_1", "type": "function", "function": { "name": "shell", "arguments": "{\"cmd\":\"ls\"}" } }]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": { "prompt_tokens": 100, "completion_tokens": 20, "prompt_tokens_details": { "cached_tokens": 60 } }
+        });
+
+        let response: Value =
+            serde_json::from_slice(&translate_response(body.to_string().as_bytes()).unwrap())
+                .unwrap();
+        assert_eq!(response["id"], "resp_chatcmpl-1");
+        assert_eq!(response["status"], "completed");
+        assert_eq!(response["output"][0]["content"][0]["text"], "Listing.");
+        assert_eq!(response["output"][1]["call_id"], "call_1");
+        assert_eq!(response["output"][1]["arguments"], "{\"cmd\":\"ls\"}");
+        assert_eq!(response["usage"]["input_tokens"], 100);
+        assert_eq!(
+            response["usage"]["input_tokens_details"]["cached_tokens"],
+            60
+        );
+    }
+
+    #[tokio::test]
+    async fn translates_stream_into_response_events() {
+        let chunks = [
+            r#"data: {"id":"c1","model":"m","choices":[{"index":0,"delta":{"role":"assistant","content":"Hi"}}]}"#,
+            r#"data: {"id":"c1","model":"m","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"shell","arguments":"{\"cmd\":"}}]}}]}"#,
+            r#"data: {"id":"c1","model":"m","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"ls\"}"}}]}}]}"#,
+            r#"data: {"id":"c1","model":"m","choices":[{"index":0,"delta":{},"finish_reason":"tool_calls"}]}"#,
+            r#"data: {"id":"c1","model":"m","choices":[],"usage":{"prompt_tokens":10,"completion_tokens":5}}"#,
+            "data: [DONE]",
+        ];
+        let stream = futures::stream::iter(
+            chunks
+                .iter()
+                .map(|c| Ok::<_, std::io::Error>(Bytes::from(format!("{}\n\n", c))))
+                .collect::<Vec<_>>(),
+        );
+        let out: Vec<u8> = translate_stream(stream)
+            .map(|chunk| chunk.unwrap().to_vec())
+            .concat()
+            .await;
+
+        let events = SseParser::new().feed(&out);
+        let types: Vec<String> = events.iter().filter_map(|e| e.event_type()).collect();
+        assert_eq!(
+            types,
+            [
+                "response.created",
+                "response.output_item.added",
+                "response.content_part.added",
+                "response.output_text.delta",
+                "response.output_text.done",
+                "response.content_part.done",
+                "response.output_item.done",
+                "response.output_item.added",
+                "response.function_call_arguments.delta",
+                "response.function_call_arguments.delta",
+                "response.function_call_arguments.done",
+                "response.output_item.done",
+                "response.completed",
+            ]
+        );
+        let call = events[11].json().unwrap();
+        assert_eq!(call["output_index"], 1);
+        assert_eq!(call["item"]["arguments"], "{\"cmd\":\"ls\"}");
+        let completed = events[12].json().unwrap();
+        assert_eq!(completed["response"]["output"].as_array().unwrap().len(), 2);
+        assert_eq!(completed["response"]["usage"]["output_tokens"], 5);
+    }
+
+    #[tokio::test]
+    async fn length_limit_ends_incomplete() {
+        let stream = futures::stream::iter(vec![Ok::<_, std::io::Error>(Bytes::from(
+            "data: {\"id\":\"c1\",\"choices\":[{\"delta\":{\"content\":\"Hi\"},\"finish_reason\":\"length\"}]}\n\n",
+        ))]);
+        let out: Vec<u8> = translate_stream(stream)
+            .map(|chunk| chunk.unwrap().to_vec())
+            .concat()
+            .await;
+
+        let last = SseParser::new().feed(&out).pop().unwrap();
+        assert_eq!(last.event_type().as_deref(), Some("response.incomplete"));
+        assert_eq!(
+            last.json().unwrap()["response"]["incomplete_details"]["reason"],
+            "max_output_tokens"
+        );
+    }
+}
@@ -0,0 +1,395 @@
+//! OpenAI Responses output built from another protocol's deltas: output items,
+//! the streamed event sequence and the final response object
+
+use serde_json::{json, Map, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn item_id(prefix: &str) -> String {
+    format!("{}_{}", prefix, hex::encode(rand::random::<[u8; 12]>()))
+}
+
+/// Response id derived from the upstream message id
+pub fn response_id(upstream_id: &Value) -> String {
+    match upstream_id.as_str() {
+        Some(id) => format!("resp_{}", id),
+        None => item_id("resp"),
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// A reasoning item; `encrypted_content` is replayed by Codex on the next turn
+pub fn reasoning_item(id: &str, text: &str, encrypted_content: Option<&str>) -> Value {
+    let summary = if text.is_empty() {
+        json!([])
+    } else {
+        json!([{ "type": "summary_text", "text": text }])
+    };
+    let mut item = json!({ "id": id, "type": "reasoning", "summary": summary });
+    if let Some(encrypted) = encrypted_content {
+        item["encrypted_content"] = encrypted.into();
+    }
+    item
+}
+
+pub fn message_item(id: &str, text: &str, status: &str) -> Value {
+    json!({
+        "id": id,
+        "type": "message",
+        "status": status,
+        "role": "assistant",
+        "content": [{ "type": "output_text", "text": text, "annotations": [] }],
+    })
+}
+
+pub fn function_call_item(id: &str, call_id: &Value, name: &Value, arguments: &str) -> Value {
+    json!({
+        "id": id,
+        "type": "function_call",
+        "status": "completed",
+        "call_id": call_id,
+        "name": name,
+        "arguments": arguments,
+    })
+}
+
+/// The full Responses object; `incomplete` is the reason output was cut short
+pub fn response_object(
+    id: &str,
+    model: &Value,
+    output: Vec<Value>,
+    incomplete: Option<&str>,
+    usage: Value,
+) -> Value {
+    let mut response = json!({
+        "id": id,
+        "object": "response",
+        "created_at": now_secs(),
+        "status": "completed",
+        "model": model,
+        "output": output,
+        "usage": usage,
+    });
+    if let Some(reason) = incomplete {
+        response["status"] = "incomplete".into();
+        response["incomplete_details"] = json!({ "reason": reason });
+    }
+    response
+}
+
+/// Output item currently streaming
+enum Item {
+    Reasoning {
+        id: String,
+        text: String,
+        encrypted_content: Option<String>,
+    },
+    Message {
+        id: String,
+        text: String,
+    },
+    Call {
+        /// Upstream index of the call, to tell deltas of parallel calls apart
+        index: u64,
+        id: String,
+        call_id: Value,
+        name: Value,
+        arguments: String,
+    },
+}
+
+/// Writes the Responses event sequence as upstream deltas arrive
+#[derive(Default)]
+pub struct ResponsesWriter {
+    started: bool,
+    id: String,
+    model: Value,
+    /// Finished output items, repeated in `response.completed`
+    output: Vec<Value>,
+    item: Option<Item>,
+}
+
+impl ResponsesWriter {
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Emit `response.created` once
+    pub fn start(&mut self, id: String, model: Value, out: &mut String) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        self.id = id;
+        self.model = model;
+        emit(
+            out,
+            "response.created",
+            json!({
+                "response": {
+                    "id": self.id,
+                    "object": "response",
+                    "created_at": now_secs(),
+                    "status": "in_progress",
+                    "model": self.model,
+                    "output": [],
+                },
+            }),
+        );
+    }
+
+    fn open_reasoning(&mut self, out: &mut String) {
+        if matches!(self.item, Some(Item::Reasoning { .. })) {
+            return;
+        }
+        self.close_item(out);
+        let id = item_id("rs");
+        emit(
+            out,
+            "response.output_item.added",
+            json!({
+                "output_index": self.output.len(),
+                "item": { "id": id, "type": "reasoning", "summary": [] },
+            }),
+        );
+        self.item = Some(Item::Reasoning {
+            id,
+            text: String::new(),
+            encrypted_content: None,
+        });
+    }
+
+    pub fn reasoning_delta(&mut self, delta: &str, out: &mut String) {
+        self.open_reasoning(out);
+        let output_index = self.output.len();
+        if let Some(Item::Reasoning { id, text, .. }) = &mut self.item {
+            text.push_str(delta);
+            emit(
+                out,
+                "response.reasoning_summary_text.delta",
+                json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "summary_index": 0,
+                    "delta": delta,
+                }),
+            );
+        }
+    }
+
+    /// Attach opaque upstream reasoning state to the current reasoning item
+    pub fn reasoning_encrypted(&mut self, encrypted: &str, out: &mut String) {
+        self.open_reasoning(out);
+        if let Some(Item::Reasoning {
+            encrypted_content, ..
+        }) = &mut self.item
+        {
+            encrypted_content
+                .get_or_insert_with(String::new)
+                .push_str(encrypted);
+        }
+    }
+
+    pub fn text_delta(&mut self, delta: &str, out: &mut String) {
+        if !matches!(self.item, Some(Item::Message { .. })) {
+            self.close_item(out);
+            let id = item_id("msg");
+            let output_index = self.output.len();
+            let mut item = message_item(&id, "", "in_progress");
+            item["content"] = json!([]);
+            emit(
+                out,
+                "response.output_item.added",
+                json!({ "output_index": output_index, "item": item }),
+            );
+            emit(
+                out,
+                "response.content_part.added",
+                json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "part": { "type": "output_text", "text": "", "annotations": [] },
+                }),
+            );
+            self.item = Some(Item::Message {
+                id,
+                text: String::new(),
+            });
+        }
+        let output_index = self.output.len();
+        if let Some(Item::Message { id, text }) = &mut self.item {
+            text.push_str(delta);
+            emit(
+                out,
+                "response.output_text.delta",
+                json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "delta": delta,
+                }),
+            );
+        }
+    }
+
+    /// Start function call `index` unless it is the one already streaming
+    pub fn open_call(&mut self, index: u64, call_id: &Value, name: &Value, out: &mut String) {
+        if matches!(self.item, Some(Item::Call { index: open, .. }) if open == index) {
+            return;
+        }
+        self.close_item(out);
+        let id = item_id("fc");
+        emit(
+            out,
+            "response.output_item.added",
+            json!({
+                "output_index": self.output.len(),
+                "item": {
+                    "id": id,
+                    "type": "function_call",
+                    "status": "in_progress",
+                    "call_id": call_id,
+                    "name": name,
+                    "arguments": "",
+                },
+            }),
+        );
+        self.item = Some(Item::Call {
+            index,
+            id,
+            call_id: call_id.clone(),
+            name: name.clone(),
+            arguments: String::new(),
+        });
+    }
+
+    /// Arguments of the call opened by `open_call`
+    pub fn call_arguments(&mut self, delta: &str, out: &mut String) {
+        let output_index = self.output.len();
+        if let Some(Item::Call { id, arguments, .. }) = &mut self.item {
+            if delta.is_empty() {
+                return;
+            }
+            arguments.push_str(delta);
+            emit(
+                out,
+                "response.function_call_arguments.delta",
+                json!({ "item_id": id, "output_index": output_index, "delta": delta }),
+            );
+        }
+    }
+
+    /// Emit the `.done` events of the open item and move it to the output
+    pub fn close_item(&mut self, out: &mut String) {
+        let output_index = self.output.len();
+        let item = match self.item.take() {
+            None => return,
+            Some(Item::Reasoning {
+                id,
+                text,
+                encrypted_content,
+            }) => {
+                if !text.is_empty() {
+                    emit(
+                        out,
+                        "response.reasoning_summary_text.done",
+                        json!({
+                            "item_id": id,
+                            "output_index": output_index,
+                            "summary_index": 0,
+                            "text": text,
+                        }),
+                    );
+                }
+                reasoning_item(&id, &text, encrypted_content.as_deref())
+            }
+            Some(Item::Message { id, text }) => {
+                emit(
+                    out,
+                    "response.output_text.done",
+                    json!({
+                        "item_id": id,
+                        "output_index": output_index,
+                        "content_index": 0,
+                        "text": text,
+                    }),
+                );
+                emit(
+                    out,
+                    "response.content_part.done",
+                    json!({
+                        "item_id": id,
+                        "output_index": output_index,
+                        "content_index": 0,
+                        "part": { "type": "output_text", "text": text, "annotations": [] },
+                    }),
+                );
+                message_item(&id, &text, "completed")
+            }
+            Some(Item::Call {
+                id,
+                call_id,
+                name,
+                arguments,
+                ..
+            }) => {
+                // A call without parameters may stream no arguments at all
+                let arguments = if arguments.is_empty() {
+                    "{}".to_string()
+                } else {
+                    arguments
+                };
+                emit(
+                    out,
+                    "response.function_call_arguments.done",
+                    json!({ "item_id": id, "output_index": output_index, "arguments": arguments }),
+                );
+                function_call_item(&id, &call_id, &name, &arguments)
+            }
+        };
+        emit(
+            out,
+            "response.output_item.done",
+            json!({ "output_index": output_index, "item": item }),
+        );
+        self.output.push(item);
+    }
+
+    /// Close the open item and emit `response.completed` (or `.incomplete`)
+    pub fn complete(&mut self, incomplete: Option<&str>, usage: Value, out: &mut String) {
+        self.close_item(out);
+        let response = response_object(
+            &self.id,
+            &self.model,
+            std::mem::take(&mut self.output),
+            incomplete,
+            usage,
+        );
+        let event = if incomplete.is_some() {
+            "response.incomplete"
+        } else {
+            "response.completed"
+        };
+        emit(out, event, json!({ "response": response }));
+    }
+}
+
+/// Write one event; Responses payloads also carry their type, first
+fn emit(out: &mut String, event: &str, data: Value) {
+    let mut payload = Map::new();
+    payload.insert("type".into(), event.into());
+    if let Value::Object(fields) = data {
+        payload.extend(fields);
+    }
+    out.push_str(&format!(
+        "event: {}\ndata: {}\n\n",
+        event,
+        Value::Object(payload)
+    ));
+}
@@ -0,0 +1,738 @@
+//! OpenAI Responses ⇄ Anthropic Messages translation, for serving Codex from
+//! Claude endpoints listed with `servesKinds: ["codex"]`
+
+use crate::responses_events::{
+    function_call_item, item_id, message_item, reasoning_item, response_id, response_object,
+    ResponsesWriter,
+};
+use crate::sse::{self, SseParser};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde_json::{json, Map, Value};
+
+/// Messages requires `max_tokens`; Codex rarely sets `max_output_tokens`
+const DEFAULT_MAX_TOKENS: u64 = 32_000;
+
+/// Marks Claude thinking signatures carried in `encrypted_content`, so they
+/// can be told apart from OpenAI's own encrypted reasoning
+const SIGNATURE_PREFIX: &str = "cc-proxy:anthropic:";
+const REDACTED_PREFIX: &str = "cc-proxy:anthropic-redacted:";
+
+/// Translate a `/responses` request body into a `/v1/messages` one
+pub fn translate_request(request: &Value) -> Value {
+    let mut system = Vec::new();
+    if let Some(instructions) = request["instructions"].as_str().filter(|s| !s.is_empty()) {
+        system.push(json!({ "type": "text", "text": instructions }));
+    }
+
+    let mut messages: Vec<Value> = Vec::new();
+    match &request["input"] {
+        Value::String(text) => push_blocks(
+            &mut messages,
+            "user",
+            vec![json!({ "type": "text", "text": text })],
+        ),
+        Value::Array(items) => {
+            for item in items {
+                input_item(item, &mut system, &mut messages);
+            }
+        }
+        _ => {}
+    }
+
+    let mut messages_request = Map::new();
+    messages_request.insert("model".into(), request["model"].clone());
+    let max_tokens = request["max_output_tokens"]
+        .as_u64()
+        .unwrap_or(DEFAULT_MAX_TOKENS);
+    messages_request.insert("max_tokens".into(), max_tokens.into());
+
+    let budget = thinking_budget(request["reasoning"]["effort"].as_str())
+        .filter(|budget| *budget < max_tokens && can_think(&messages));
+    if let Some(budget) = budget {
+        messages_request.insert(
+            "thinking".into(),
+            json!({ "type": "enabled", "budget_tokens": budget }),
+        );
+    } else {
+        // Sampling parameters are rejected while thinking
+        for key in ["temperature", "top_p"] {
+            if !request[key].is_null() {
+                messages_request.insert(key.into(), request[key].clone());
+            }
+        }
+        strip_thinking(&mut messages);
+    }
+
+    // Cache the stable prefix, as Claude Code would
+    if let Some(last) = system.last_mut() {
+        last["cache_control"] = json!({ "type": "ephemeral" });
+    }
+    if let Some(last) = messages
+        .last_mut()
+        .and_then(|m| m["content"].as_array_mut())
+        .and_then(|blocks| blocks.last_mut())
+    {
+        last["cache_control"] = json!({ "type": "ephemeral" });
+    }
+    if !system.is_empty() {
+        messages_request.insert("system".into(), system.into());
+    }
+    messages_request.insert("messages".into(), messages.into());
+
+    if request["stream"].as_bool() == Some(true) {
+        messages_request.insert("stream".into(), true.into());
+    }
+
+    // Built-in tools (local_shell, web_search, custom grammars) have no Messages equivalent
+    let mut tools: Vec<Value> = request["tools"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|tool| tool["type"] == "function")
+        .map(|tool| {
+            let mut converted = json!({
+                "name": tool["name"],
+                "input_schema": tool["parameters"],
+            });
+            if let Some(description) = tool["description"].as_str() {
+                converted["description"] = description.into();
+            }
+            converted
+        })
+        .collect();
+    if let Some(last) = tools.last_mut() {
+        last["cache_control"] = json!({ "type": "ephemeral" });
+    }
+    if !tools.is_empty() {
+        messages_request.insert("tools".into(), tools.into());
+        let choice = &request["tool_choice"];
+        let mut tool_choice = match choice.as_str() {
+            Some("required") => json!({ "type": "any" }),
+            Some("none") => json!({ "type": "none" }),
+            _ if choice["type"] == "function" => json!({ "type": "tool", "name": choice["name"] }),
+            _ => json!({ "type": "auto" }),
+        };
+        if request["parallel_tool_calls"].as_bool() == Some(false) {
+            tool_choice["disable_parallel_tool_use"] = true.into();
+        }
+        messages_request.insert("tool_choice".into(), tool_choice);
+    }
+
+    Value::Object(messages_request)
+}
+
+fn thinking_budget(effort: Option<&str>) -> Option<u64> {
+    match effort? {
+        "low" => Some(4_096),
+        "medium" => Some(10_240),
+        "high" => Some(24_576),
+        _ => None,
+    }
+}
+
+/// With thinking on, an assistant turn that is still waiting on tool results
+/// must start with the thinking that led to its calls. Turns produced by
+/// another provider have none, so thinking stays off until the loop ends.
+fn can_think(messages: &[Value]) -> bool {
+    let Some(assistant) = messages.iter().rev().find(|m| m["role"] == "assistant") else {
+        return true;
+    };
+    let blocks = assistant["content"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let calls_tools = blocks.iter().any(|b| b["type"] == "tool_use");
+    let starts_with_thinking = blocks
+        .first()
+        .is_some_and(|b| b["type"] == "thinking" || b["type"] == "redacted_thinking");
+    !calls_tools || starts_with_thinking
+}
+
+fn strip_thinking(messages: &mut [Value]) {
+    for message in messages {
+        if let Some(blocks) = message["content"].as_array_mut() {
+            blocks.retain(|b| b["type"] != "thinking" && b["type"] != "redacted_thinking");
+        }
+    }
+}
+
+/// Append blocks to the conversation, merging consecutive turns of one role
+fn push_blocks(messages: &mut Vec<Value>, role: &str, blocks: Vec<Value>) {
+    if blocks.is_empty() {
+        return;
+    }
+    if let Some(last) = messages.last_mut().filter(|m| m["role"] == role) {
+        if let Some(content) = last["content"].as_array_mut() {
+            content.extend(blocks);
+            return;
+        }
+    }
+    messages.push(json!({ "role": role, "content": blocks }));
+}
+
+/// Append one Responses input item; system and developer text joins `system`
+fn input_item(item: &Value, system: &mut Vec<Value>, messages: &mut Vec<Value>) {
+    // Items without a type are plain `{role, content}` messages
+    match item["type"].as_str().unwrap_or("message") {
+        "message" => {
+            let blocks = content_blocks(&item["content"]);
+            match item["role"].as_str() {
+                Some("system") | Some("developer") => {
+                    system.extend(blocks.into_iter().filter(|block| block["type"] == "text"))
+                }
+                Some("assistant") => push_blocks(messages, "assistant", blocks),
+                _ => push_blocks(messages, "user", blocks),
+            }
+        }
+        "reasoning" => {
+            // Only Claude's own thinking can be replayed; other reasoning is opaque
+            let encrypted = item["encrypted_content"].as_str().unwrap_or_default();
+            let block = if let Some(signature) = encrypted.strip_prefix(SIGNATURE_PREFIX) {
+                let thinking: Vec<&str> = item["summary"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|part| part["text"].as_str())
+                    .collect();
+                json!({ "type": "thinking", "thinking": thinking.join(""), "signature": signature })
+            } else if let Some(data) = encrypted.strip_prefix(REDACTED_PREFIX) {
+                json!({ "type": "redacted_thinking", "data": data })
+            } else {
+                return;
+            };
+            push_blocks(messages, "assistant", vec![block]);
+        }
+        "function_call" => {
+            let arguments = item["arguments"].as_str().unwrap_or("{}");
+            let input = serde_json::from_str::<Value>(arguments).unwrap_or_else(|_| json!({}));
+            push_blocks(
+                messages,
+                "assistant",
+                vec![json!({
+                    "type": "tool_use",
+                    "id": item["call_id"],
+                    "name": item["name"],
+                    "input": input,
+                })],
+            );
+        }
+        "function_call_output" => {
+            let output = &item["output"];
+            let content = match output {
+                Value::String(text) => text.clone(),
+                _ => output
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|part| part["text"].as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            };
+            push_blocks(
+                messages,
+                "user",
+                vec![json!({
+                    "type": "tool_result",
+                    "tool_use_id": item["call_id"],
+                    "content": content,
+                })],
+            );
+        }
+        _ => {}
+    }
+}
+
+/// Messages content blocks of a Responses message
+fn content_blocks(content: &Value) -> Vec<Value> {
+    let Some(parts) = content.as_array() else {
+        return match content.as_str() {
+            Some(text) if !text.is_empty() => vec![json!({ "type": "text", "text": text })],
+            _ => Vec::new(),
+        };
+    };
+    parts
+        .iter()
+        .filter_map(|part| match part["type"].as_str()? {
+            "input_text" | "output_text" => part["text"]
+                .as_str()
+                .filter(|text| !text.is_empty())
+                .map(|text| json!({ "type": "text", "text": text })),
+            "input_image" => image_block(part["image_url"].as_str()?),
+            _ => None,
+        })
+        .collect()
+}
+
+fn image_block(url: &str) -> Option<Value> {
+    let source = match url.strip_prefix("data:") {
+        Some(data_url) => {
+            let (media_type, data) = data_url.split_once(";base64,")?;
+            json!({ "type": "base64", "media_type": media_type, "data": data })
+        }
+        None => json!({ "type": "url", "url": url }),
+    };
+    Some(json!({ "type": "image", "source": source }))
+}
+
+/// Responses usage from an Anthropic `usage` object; input includes cached
+/// tokens. Cache writes have no Responses field, so Anthropic's are kept for
+/// the proxy's own accounting.
+fn usage_of(usage: &Value) -> Value {
+    let field = |name: &str| usage[name].as_u64().unwrap_or(0);
+    let cached = field("cache_read_input_tokens");
+    let written = field("cache_creation_input_tokens");
+    let input = field("input_tokens") + cached + written;
+    let output = field("output_tokens");
+    let mut translated = json!({
+        "input_tokens": input,
+        "input_tokens_details": { "cached_tokens": cached },
+        "output_tokens": output,
+        "output_tokens_details": { "reasoning_tokens": 0 },
+        "total_tokens": input + output,
+    });
+    if written > 0 {
+        translated["cache_creation_input_tokens"] = written.into();
+    }
+    if usage["cache_creation"].is_object() {
+        translated["cache_creation"] = usage["cache_creation"].clone();
+    }
+    translated
+}
+
+/// Why a Responses output was cut short, from the Messages stop reason
+fn incomplete_reason(stop_reason: &str) -> Option<&'static str> {
+    match stop_reason {
+        "max_tokens" => Some("max_output_tokens"),
+        "refusal" => Some("content_filter"),
+        _ => None,
+    }
+}
+
+/// Translate a buffered `/v1/messages` response into a Responses object
+pub fn translate_response(body: &[u8]) -> Option<Bytes> {
+    let message: Value = serde_json::from_slice(body).ok()?;
+    let blocks = message["content"].as_array()?;
+
+    let mut output = Vec::new();
+    for block in blocks {
+        match block["type"].as_str() {
+            Some("thinking") => {
+                let signature = format!(
+                    "{}{}",
+                    SIGNATURE_PREFIX,
+                    block["signature"].as_str().unwrap_or_default()
+                );
+                output.push(reasoning_item(
+                    &item_id("rs"),
+                    block["thinking"].as_str().unwrap_or_default(),
+                    Some(&signature),
+                ));
+            }
+            Some("redacted_thinking") => {
+                let data = format!(
+                    "{}{}",
+                    REDACTED_PREFIX,
+                    block["data"].as_str().unwrap_or_default()
+                );
+                output.push(reasoning_item(&item_id("rs"), "", Some(&data)));
+            }
+            Some("text") => {
+                let text = block["text"].as_str().unwrap_or_default();
+                output.push(message_item(&item_id("msg"), text, "completed"));
+            }
+            Some("tool_use") => output.push(function_call_item(
+                &item_id("fc"),
+                &block["id"],
+                &block["name"],
+                &block["input"].to_string(),
+            )),
+            _ => {}
+        }
+    }
+
+    let response = response_object(
+        &response_id(&message["id"]),
+        &message["model"],
+        output,
+        incomplete_reason(message["stop_reason"].as_str().unwrap_or("end_turn")),
+        usage_of(&message["usage"]),
+    );
+    serde_json::to_vec(&response).ok().map(Bytes::from)
+}
+
+/// Rebuilds the Responses event sequence from Messages stream events
+#[derive(Default)]
+struct StreamTranslator {
+    parser: SseParser,
+    writer: ResponsesWriter,
+    finished: bool,
+    stop_reason: Option<String>,
+    /// Anthropic usage, merged from `message_start` and `message_delta`
+    usage: Map<String, Value>,
+}
+
+impl StreamTranslator {
+    fn feed(&mut self, chunk: &[u8]) -> Bytes {
+        let mut out = String::new();
+        for event in self.parser.feed(chunk) {
+            if self.finished {
+                continue;
+            }
+            if let Some(data) = event.json() {
+                self.event(&data, &mut out);
+            }
+        }
+        Bytes::from(out)
+    }
+
+    /// Called when the upstream stream ends
+    fn finish(&mut self) -> Bytes {
+        let mut out = String::new();
+        if !self.finished {
+            if self.stop_reason.is_some() {
+                self.close(&mut out);
+            } else {
+                self.finished = true;
+                out.push_str(&String::from_utf8_lossy(&sse::error_event(
+                    "codex",
+                    "server_error",
+                    "Upstream stream ended unexpectedly",
+                )));
+            }
+        }
+        Bytes::from(out)
+    }
+
+    fn merge_usage(&mut self, usage: &Value) {
+        if let Some(fields) = usage.as_object() {
+            for (key, value) in fields.iter().filter(|(_, v)| v.is_u64()) {
+                self.usage.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    fn event(&mut self, data: &Value, out: &mut String) {
+        match data["type"].as_str().unwrap_or_default() {
+            "message_start" => {
+                let message = &data["message"];
+                self.writer
+                    .start(response_id(&message["id"]), message["model"].clone(), out);
+                self.merge_usage(&message["usage"]);
+            }
+            "content_block_start" => {
+                let block = &data["content_block"];
+                match block["type"].as_str() {
+                    Some("tool_use") => {
+                        let index = data["index"].as_u64().unwrap_or(0);
+                        self.writer
+                            .open_call(index, &block["id"], &block["name"], out);
+                    }
+                    Some("redacted_thinking") => {
+                        let data = block["data"].as_str().unwrap_or_default();
+                        self.writer
+                            .reasoning_encrypted(&format!("{}{}", REDACTED_PREFIX, data), out);
+                    }
+                    _ => {}
+                }
+            }
+            "content_block_delta" => {
+                let delta = &data["delta"];
+                let text = |key: &str| delta[key].as_str().unwrap_or_default();
+                match delta["type"].as_str() {
+                    Some("text_delta") if !text("text").is_empty() => {
+                        self.writer.text_delta(text("text"), out)
+                    }
+                    Some("thinking_delta") if !text("thinking").is_empty() => {
+                        self.writer.reasoning_delta(text("thinking"), out)
+                    }
+                    Some("signature_delta") => self.writer.reasoning_encrypted(
+                        &format!("{}{}", SIGNATURE_PREFIX, text("signature")),
+                        out,
+                    ),
+                    Some("input_json_delta") => {
+                        self.writer.call_arguments(text("partial_json"), out)
+                    }
+                    _ => {}
+                }
+            }
+            "content_block_stop" => self.writer.close_item(out),
+            "message_delta" => {
+                if let Some(reason) = data["delta"]["stop_reason"].as_str() {
+                    self.stop_reason = Some(reason.to_string());
+                }
+                self.merge_usage(&data["usage"]);
+            }
+            "message_stop" => self.close(out),
+            "error" => {
+                let error = &data["error"];
+                let code = error["type"].as_str().unwrap_or("server_error");
+                let message = error["message"].as_str().unwrap_or("Upstream error");
+                out.push_str(&String::from_utf8_lossy(&sse::error_event(
+                    "codex", code, message,
+                )));
+                self.finished = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn close(&mut self, out: &mut String) {
+        if !self.writer.is_started() {
+            self.writer
+                .start(response_id(&Value::Null), Value::Null, out);
+        }
+        let usage = usage_of(&Value::Object(self.usage.clone()));
+        let incomplete = incomplete_reason(self.stop_reason.as_deref().unwrap_or("end_turn"));
+        self.writer.complete(incomplete, usage, out);
+        self.finished = true;
+    }
+}
+
+/// Translate a `/v1/messages` SSE stream into Responses SSE events
+pub fn translate_stream<S, E>(stream: S) -> impl Stream<Item = Result<Bytes, E>> + Send
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+    E: Send + 'static,
+{
+    futures::stream::unfold(
+        Some((stream, StreamTranslator::default())),
+        |state| async move {
+            let (mut stream, mut translator) = state?;
+            match stream.next().await {
+                Some(Ok(chunk)) => Some((Ok(translator.feed(&chunk)), Some((stream, translator)))),
+                Some(Err(e)) => Some((Err(e), Some((stream, translator)))),
+                None => Some((Ok(translator.finish()), None)),
+            }
+        },
+    )
+}
+
+/// Quick check before parsing a request for `strip_claude_reasoning`
+pub fn has_claude_reasoning(body: &[u8]) -> bool {
+    let marker = b"cc-proxy:anthropic";
+    body.windows(marker.len()).any(|window| window == marker)
+}
+
+/// Drop Claude thinking from a Responses request bound for an OpenAI
+/// upstream, which can't decrypt it. Returns whether anything was removed.
+pub fn strip_claude_reasoning(request: &mut Value) -> bool {
+    let Some(items) = request["input"].as_array_mut() else {
+        return false;
+    };
+    let before = items.len();
+    items.retain(|item| {
+        let encrypted = item["encrypted_content"].as_str().unwrap_or_default();
+        !(item["type"] == "reasoning"
+            && (encrypted.starts_with(SIGNATURE_PREFIX) || encrypted.starts_with(REDACTED_PREFIX)))
+    });
+    items.len() != before
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_request_with_calls_outputs_and_thinking() {
+        let request = json!({
+            "model": "claude-sonnet-4-5",
+            "instructions": "You are Codex.",
+            "stream": true,
+            "reasoning": { "effort": "medium" },
+            "tools": [
+                { "type": "function", "name": "shell", "parameters": { "type": "object" } },
+                { "type": "local_shell" }
+            ],
+            "tool_choice": "auto",
+            "parallel_tool_calls": false,
+            "input": [
+                { "type": "message", "role": "developer", "content": [{ "type": "input_text", "text": "Sandbox: read-only" }] },
+                { "type": "message", "role": "user", "content": [{ "type": "input_text", "text": "List files" }] },
+                { "type": "reasoning", "summary": [{ "type": "summary_text", "text": "Use ls." }], "encrypted_content": "cc-proxy:anthropic:sig1" },
+                { "type": "reasoning", "summary": [], "encrypted_content": "gAAAAopenai" },
+                { "type": "function_call", "call_id": "toolu_1", "name": "shell", "arguments": "{\"cmd\":\"ls\"}" },
+                { "type": "function_call_output", "call_id": "toolu_1", "output": "a.txt" }
+            ]
+        });
+
+        let messages_request = translate_request(&request);
+        assert_eq!(messages_request["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert_eq!(messages_request["thinking"]["budget_tokens"], 10_240);
+        assert_eq!(messages_request["system"][1]["text"], "Sandbox: read-only");
+        assert_eq!(messages_request["tools"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            messages_request["tool_choice"],
+            json!({ "type": "auto", "disable_parallel_tool_use": true })
+        );
+
+        let messages = messages_request["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(
+            messages[1]["content"][0],
+            json!({ "type": "thinking", "thinking": "Use ls.", "signature": "sig1" })
+        );
+        assert_eq!(messages[1]["content"][1]["input"]["cmd"], "ls");
+        assert_eq!(messages[2]["content"][0]["tool_use_id"], "toolu_1");
+        assert_eq!(
+            messages[2]["content"][0]["cache_control"]["type"],
+            "ephemeral"
+        );
+    }
+
+    #[test]
+    fn thinking_stays_off_mid_loop_without_claude_reasoning() {
+        let request = json!({
+            "model": "claude-sonnet-4-5",
+            "reasoning": { "effort": "high" },
+            "input": [
+                { "type": "message", "role": "user", "content": "List files" },
+                { "type": "reasoning", "summary": [], "encrypted_content": "gAAAAopenai" },
+                { "type": "function_call", "call_id": "call_1", "name": "shell", "arguments": "{}" },
+                { "type": "function_call_output", "call_id": "call_1", "output": "a.txt" }
+            ]
+        });
+
+        assert!(translate_request(&request)["thinking"].is_null());
+    }
+
+    #[test]
+    fn round_trips_thinking_signatures() {
+        let body = json!({
+            "id": "msg_1",
+            "model": "claude-sonnet-4-5",
+            "content": [
+                { "type": "thinking", "thinking": "Use ls.", "signature": "sig1" },
+                { "type": "tool_use", "id": "toolu_1", "name": "shell", "input": { "cmd": "ls" } }
+            ],
+            "stop_reason": "tool_use",
+            "usage": { "input_tokens": 10, "cache_read_input_tokens": 90, "output_tokens": 5 }
+        });
+
+        let mut response: Value =
+            serde_json::from_slice(&translate_response(body.to_string().as_bytes()).unwrap())
+                .unwrap();
+        assert_eq!(response["status"], "completed");
+        assert_eq!(response["output"][1]["arguments"], "{\"cmd\":\"ls\"}");
+        assert_eq!(response["usage"]["input_tokens"], 100);
+        assert_eq!(
+            response["usage"]["input_tokens_details"]["cached_tokens"],
+            90
+        );
+
+        // Codex replays the output as input on the next turn
+        let mut next = json!({
+            "model": "claude-sonnet-4-5",
+            "reasoning": { "effort": "low" },
+            "input": response["output"].take(),
+        });
+        let replayed = translate_request(&next);
+        assert_eq!(replayed["messages"][0]["content"][0]["signature"], "sig1");
+
+        assert!(strip_claude_reasoning(&mut next));
+        assert_eq!(next["input"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn translates_stream_into_response_events() {
+        let events = [
+            (
+                "message_start",
+                json!({ "type": "message_start", "message": { "id": "msg_1", "model": "claude-sonnet-4-5", "usage": { "input_tokens": 10, "output_tokens": 1 } } }),
+            ),
+            (
+                "content_block_start",
+                json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "thinking", "thinking": "" } }),
+            ),
+            (
+                "content_block_delta",
+                json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "thinking_delta", "thinking": "Hmm" } }),
+            ),
+            (
+                "content_block_delta",
+                json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "signature_delta", "signature": "sig" } }),
+            ),
+            (
+                "content_block_stop",
+                json!({ "type": "content_block_stop", "index": 0 }),
+            ),
+            (
+                "content_block_start",
+                json!({ "type": "content_block_start", "index": 1, "content_block": { "type": "tool_use", "id": "toolu_1", "name": "shell", "input": {} } }),
+            ),
+            (
+                "content_block_delta",
+                json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "input_json_delta", "partial_json": "{\"cmd\":\"ls\"}" } }),
+            ),
+            (
+                "content_block_stop",
+                json!({ "type": "content_block_stop", "index": 1 }),
+            ),
+            (
+                "message_delta",
+                json!({ "type": "message_delta", "delta": { "stop_reason": "tool_use" }, "usage": { "output_tokens": 7 } }),
+            ),
+            ("message_stop", json!({ "type": "message_stop" })),
+        ];
+        let stream = futures::stream::iter(
+            events
+                .iter()
+                .map(|(event, data)| {
+                    Ok::<_, std::io::Error>(Bytes::from(format!(
+                        "event: {}\ndata: {}\n\n",
+                        event, data
+                    )))
+                })
+                .collect::<Vec<_>>(),
+        );
+        let out: Vec<u8> = translate_stream(stream)
+            .map(|chunk| chunk.unwrap().to_vec())
+            .concat()
+            .await;
+
+        let events = SseParser::new().feed(&out);
+        let types: Vec<String> = events.iter().filter_map(|e| e.event_type()).collect();
+        assert_eq!(
+            types,
+            [
+                "response.created",
+                "response.output_item.added",
+                "response.reasoning_summary_text.delta",
+                "response.reasoning_summary_text.done",
+                "response.output_item.done",
+                "response.output_item.added",
+                "response.function_call_arguments.delta",
+                "response.function_call_arguments.done",
+                "response.output_item.done",
+                "response.completed",
+            ]
+        );
+        let reasoning = events[4].json().unwrap();
+        assert_eq!(
+            reasoning["item"]["encrypted_content"],
+            "cc-proxy:anthropic:sig"
+        );
+        let completed = events[9].json().unwrap();
+        assert_eq!(completed["response"]["output"][1]["call_id"], "toolu_1");
+        assert_eq!(completed["response"]["usage"]["output_tokens"], 7);
+    }
+
+    #[test]
+    fn keeps_cache_writes_for_accounting() {
+        let usage = usage_of(&json!({
+            "input_tokens": 10,
+            "cache_read_input_tokens": 90,
+            "cache_creation_input_tokens": 2000,
+            "cache_creation": { "ephemeral_5m_input_tokens": 0, "ephemeral_1h_input_tokens": 2000 },
+            "output_tokens": 5
+        }));
+        assert_eq!(usage["input_tokens"], 2100);
+
+        let mut observed = crate::usage::Usage::default();
+        observed.update(&usage);
+        assert_eq!(observed.input_tokens, 10);
+        assert_eq!(observed.cache_read_tokens, 90);
+        assert_eq!(observed.cache_write_tokens, 2000);
+        assert_eq!(observed.cache_write_1h_tokens, 2000);
+    }
+}
@@ -1,41 +1,128 @@
-use crate::cache_affinity::{hash_string, CacheAffinityManager};
-use crate::provider::{load_providers, Provider};
+use crate::auth::{self, ClientToken};
+use crate::balancer::{Balancer, Strategy};
+use crate::cache_affinity::{hash_string, session_id, CacheAffinityManager};
+use crate::circuit_breaker::{BreakerSnapshot, BreakerState, CircuitBreaker};
+use crate::config::{load_proxy_config, ProxyConfig};
+use crate::messages_chat;
+use crate::metrics::{self, ErrorRate, Metrics};
+use crate::model_rules::{
+    restore_json_model, restore_sse_model, rewrite_request_model, ModelFilter, ModelMap,
+};
+use crate::pricing::{estimate_cost, PriceRule};
+use crate::provider::{load_providers, AuthScheme, Protocol, Provider};
+use crate::quota::{QuotaExceeded, QuotaPermit, QuotaTracker};
+use crate::rate_limits::{RateLimitSnapshot, RateLimitTracker};
+use crate::responses_chat;
+use crate::responses_messages;
+use crate::sse::{self, EventClass, SseParser};
+use crate::timeouts::{with_idle_timeout, TimeoutConfig, Timeouts};
+use crate::upstream_error::{
+    native_error_body, AllProvidersFailed, AttemptError, StatusPolicy, UpstreamFailure,
+};
+use crate::usage::{
+    observe_usage, read_usage_file, write_usage_file, RequestCost, Usage, UsageRecord, UsageStats,
+};
 use anyhow::{Context, Result};
 use async_compression::tokio::bufread::GzipDecoder;
 use axum::{
     body::Body,
-    http::{HeaderMap, HeaderValue, Response, StatusCode},
+    http::{HeaderMap, HeaderValue, Method, Response, StatusCode},
 };
-use bytes::Bytes;
-use futures::TryStreamExt;
+use bytes::{Bytes, BytesMut};
+use futures::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::sync::Arc;
-use std::time::Instant;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tokio_util::io::StreamReader;
 
+const MAX_ERROR_BODY_BYTES: usize = 1024 * 1024;
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+// Stop holding back an SSE stream that produces this much without content
+const MAX_PREFETCH_BYTES: usize = 256 * 1024;
+// Largest non-streaming response buffered to map the model name back
+const MAX_RESPONSE_REWRITE_BYTES: usize = 64 * 1024 * 1024;
+
 #[derive(Clone)]
 struct ResolvedProvider {
     kind: String,
     api_url: String,
     api_key: String,
+    auth_scheme: AuthScheme,
+    auth_header: Option<String>,
+    protocol: Protocol,
     name: Option<String>,
     level: i32,
+    weight: u32,
+    status_policy: StatusPolicy,
+    timeouts: TimeoutConfig,
+    model_filter: ModelFilter,
+    model_map: ModelMap,
+    map_response_model: bool,
+    /// Endpoint of another kind, serving this kind through `servesKinds`
+    cross_kind: bool,
 }
 
 #[derive(Clone)]
 pub struct Router {
     affinity_manager: Arc<CacheAffinityManager>,
-    http_client: reqwest::Client,
+    // HTTP clients keyed by connect timeout (reqwest sets it per client)
+    http_clients: Arc<Mutex<HashMap<Option<Duration>, reqwest::Client>>>,
     // Cached providers with platform-specific configs
     cached_providers: Arc<RwLock<Vec<ResolvedProvider>>>,
+    // Proxy-wide settings from provider.json
+    config: Arc<RwLock<ProxyConfig>>,
+    balancer: Arc<Balancer>,
+    circuit_breaker: Arc<CircuitBreaker>,
+    // Token totals per provider / model / client
+    usage_stats: Arc<UsageStats>,
+    metrics: Arc<Metrics>,
+    // Provider ids switched off at runtime through the admin API
+    disabled: Arc<RwLock<HashSet<String>>>,
+    // Per-client request rates and open streams
+    quotas: Arc<QuotaTracker>,
+    // Upstream quota reported in provider rate-limit headers
+    rate_limits: Arc<RateLimitTracker>,
+}
+
+/// A resolved provider endpoint and its health, as shown by the admin API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderStatus {
+    pub id: String,
+    /// `name (apiUrl)` as used in logs and metrics
+    pub label: String,
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename = "apiUrl")]
+    pub api_url: String,
+    pub level: i32,
+    pub weight: u32,
+    pub enabled: bool,
+    pub circuit: BreakerState,
+    #[serde(rename = "consecutiveFailures")]
+    pub consecutive_failures: u32,
+    #[serde(rename = "retryInSecs", skip_serializing_if = "Option::is_none")]
+    pub retry_in_secs: Option<u64>,
+    /// Upstream quota from the provider's rate-limit headers
+    #[serde(rename = "rateLimit", skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitSnapshot>,
+    /// What the endpoint speaks, when it differs from the client's protocol
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<Protocol>,
+    /// Only tried once this kind's own providers are exhausted
+    #[serde(rename = "crossKind", default, skip_serializing_if = "is_false")]
+    pub cross_kind: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
 }
 
 impl Router {
-    pub fn new(
-        affinity_manager: Arc<CacheAffinityManager>,
-        http_client: reqwest::Client,
-    ) -> Result<Self> {
+    pub fn new(affinity_manager: Arc<CacheAffinityManager>) -> Result<Self> {
         let providers = match Self::load_and_flatten_providers() {
             Ok(providers) => providers,
             Err(e) => {
@@ -44,10 +131,26 @@ impl Router {
             }
         };
 
+        let config = match load_proxy_config() {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::warn!("Failed to load proxy settings: {}", e);
+                ProxyConfig::default()
+            }
+        };
+
         Ok(Self {
             affinity_manager,
-            http_client,
+            http_clients: Arc::new(Mutex::new(HashMap::new())),
             cached_providers: Arc::new(RwLock::new(providers)),
+            config: Arc::new(RwLock::new(config)),
+            balancer: Arc::new(Balancer::new()),
+            circuit_breaker: Arc::new(CircuitBreaker::new()),
+            usage_stats: Arc::new(UsageStats::from_records(read_usage_file())),
+            metrics: Arc::new(Metrics::new()),
+            disabled: Arc::new(RwLock::new(HashSet::new())),
+            quotas: Arc::new(QuotaTracker::new()),
+            rate_limits: Arc::new(RateLimitTracker::new()),
         })
     }
 
@@ -56,9 +159,12 @@ impl Router {
         tracing::info!("Reloading providers from config file");
 
         let providers = Self::load_and_flatten_providers()?;
+        let config = load_proxy_config()?;
         let count = providers.len();
-        let mut cache = self.cached_providers.write().await;
-        *cache = providers;
+        self.affinity_manager
+            .set_default_ttl(config.affinity.ttl_secs);
+        *self.cached_providers.write().await = providers;
+        *self.config.write().await = config;
 
         tracing::info!("✓ Reloaded {} provider endpoints", count);
         Ok(())
@@ -70,12 +176,14 @@ impl Router {
         let resolved = Self::flatten_providers(providers);
         let codex_count = resolved.iter().filter(|p| p.kind == "codex").count();
         let claude_count = resolved.iter().filter(|p| p.kind == "claude").count();
+        let openai_count = resolved.iter().filter(|p| p.kind == "openai").count();
 
         tracing::info!(
-            "Loaded {} provider endpoints (codex={}, claude={})",
+            "Loaded {} provider endpoints (codex={}, claude={}, openai={})",
             resolved.len(),
             codex_count,
-            claude_count
+            claude_count,
+            openai_count
         );
 
         Ok(resolved)
@@ -85,15 +193,81 @@ impl Router {
         let mut resolved = Vec::new();
 
         for provider in providers.into_iter().filter(|p| p.enabled) {
-            for kind in ["codex", "claude"] {
+            for kind in ["codex", "claude", "openai"] {
                 if let Some(config) = provider.get_platform_config(kind) {
-                    if !config.api_url.is_empty() && !config.api_key.is_empty() {
+                    let has_credentials =
+                        !config.api_key.is_empty() || config.auth_scheme == AuthScheme::None;
+                    if config.auth_scheme == AuthScheme::CustomHeader
+                        && config.auth_header.as_deref().unwrap_or("").is_empty()
+                    {
+                        tracing::warn!(
+                            "Skipping {} provider {}: authScheme custom-header requires authHeader",
+                            kind,
+                            config.api_url
+                        );
+                        continue;
+                    }
+
+                    if config.api_url.is_empty() || !has_credentials {
+                        continue;
+                    }
+                    let protocol = config.protocol.unwrap_or(Protocol::native(kind));
+
+                    // The endpoint's own kind, then any it also serves as a fallback
+                    let cross_kinds = provider.serves_kinds.iter().filter(|k| *k != kind);
+                    for serves in std::iter::once(kind).chain(cross_kinds.map(String::as_str)) {
+                        let cross_kind = serves != kind;
+                        if !Self::can_translate(serves, protocol) {
+                            tracing::warn!(
+                                "Skipping {} provider {}: protocol {:?} is not supported for {} clients",
+                                kind,
+                                config.api_url,
+                                protocol,
+                                serves
+                            );
+                            continue;
+                        }
+                        // Another kind's model names mean nothing upstream; only mapped ones are served
+                        let model_filter = if cross_kind {
+                            if provider.model_map.is_empty() {
+                                tracing::warn!(
+                                    "Skipping {} provider {} for {} clients: servesKinds requires a modelMap",
+                                    kind,
+                                    config.api_url,
+                                    serves
+                                );
+                                continue;
+                            }
+                            ModelFilter {
+                                allow: provider.model_map.keys().cloned().collect(),
+                                deny: provider.exclude_models.clone(),
+                            }
+                        } else {
+                            ModelFilter {
+                                allow: provider.models.clone(),
+                                deny: provider.exclude_models.clone(),
+                            }
+                        };
+
                         resolved.push(ResolvedProvider {
-                            kind: kind.to_string(),
-                            api_url: config.api_url,
-                            api_key: config.api_key,
+                            kind: serves.to_string(),
+                            api_url: config.api_url.clone(),
+                            api_key: config.api_key.clone(),
+                            auth_scheme: config.auth_scheme,
+                            auth_header: config.auth_header.clone(),
+                            protocol,
                             name: provider.name.clone(),
                             level: provider.level,
+                            weight: provider.weight,
+                            status_policy: StatusPolicy {
+                                retryable: provider.retryable_statuses.clone(),
+                                non_retryable: provider.non_retryable_statuses.clone(),
+                            },
+                            timeouts: provider.timeouts.clone(),
+                            model_filter,
+                            model_map: ModelMap::new(&provider.model_map),
+                            map_response_model: provider.map_response_model,
+                            cross_kind,
                         });
                     }
                 }
@@ -103,43 +277,83 @@ impl Router {
         resolved
     }
 
-    /// Route a request to the appropriate provider
+    /// The configured client whose token the request presents
+    pub async fn authenticate(&self, headers: &HeaderMap) -> Option<ClientToken> {
+        let config = self.config.read().await;
+        auth::authenticate(&config.clients, headers).cloned()
+    }
+
+    /// Admit a request from `client` against its rate limits and daily budget;
+    /// `streaming` requests also take one of its stream slots
+    pub fn acquire_quota(
+        &self,
+        client: &ClientToken,
+        streaming: bool,
+    ) -> Result<QuotaPermit, QuotaExceeded> {
+        let today = self.usage_stats.client_today(&client.name);
+        let tokens =
+            today.usage.input_tokens + today.usage.output_tokens + today.usage.cache_write_tokens;
+        self.quotas.acquire(
+            &client.name,
+            &client.limits,
+            tokens,
+            today.cost_usd,
+            streaming,
+        )
+    }
+
+    /// Route a request from an authenticated `client` to the appropriate provider
     pub async fn route_request(
         &self,
         kind: &str,
+        method: &Method,
         endpoint: &str,
         body: Bytes,
         headers: HeaderMap,
+        client: &str,
     ) -> Result<Response<Body>> {
         let start_time = Instant::now();
 
-        // Step 1: Extract request info
-        let request_json: Value =
-            serde_json::from_slice(&body).context("Failed to parse request body as JSON")?;
+        // Step 1: Extract request info. Auxiliary endpoints (count_tokens,
+        // models, ...) may have no body and are forwarded as they are.
+        let path = endpoint.split('?').next().unwrap_or_default();
+        let auxiliary = !Self::is_model_endpoint(kind, path);
+        let request_json: Value = if auxiliary {
+            serde_json::from_slice(&body).unwrap_or(Value::Null)
+        } else {
+            serde_json::from_slice(&body).context("Failed to parse request body as JSON")?
+        };
 
-        let model = request_json["model"]
-            .as_str()
-            .unwrap_or("unknown")
-            .to_string();
+        let requested_model = request_json["model"].as_str();
+        let model = requested_model.unwrap_or("unknown").to_string();
 
-        let user_id = self.extract_user_id(&headers);
-        let affinity_key = CacheAffinityManager::generate_key(&user_id, kind, &model);
+        let session_id = session_id(kind, &request_json, &headers);
+        let affinity_key = CacheAffinityManager::generate_key(&session_id, kind, &model);
 
         tracing::debug!(
-            "Request: kind={}, model={}, user_id={}",
+            "Request: kind={}, model={}, session={}",
             kind,
             model,
-            user_id
+            session_id
         );
 
-        // Step 2: Check cache affinity
-        let cached_provider_id = self.affinity_manager.get(&affinity_key).await;
+        // Step 2: Check cache affinity (auxiliary calls must not keep a pin alive)
+        let cached_provider_id = if auxiliary {
+            None
+        } else {
+            self.affinity_manager.get(&affinity_key).await
+        };
 
         // Step 3: Get cached providers (no disk I/O!)
+        let disabled = self.disabled.read().await.clone();
         let providers_lock = self.cached_providers.read().await;
         let providers: Vec<ResolvedProvider> = providers_lock
             .iter()
             .filter(|p| p.kind == kind)
+            .filter(|p| p.model_filter.allows(&model) || (auxiliary && requested_model.is_none()))
+            // Only endpoints speaking the client's own API know its auxiliary routes
+            .filter(|p| !auxiliary || !Self::translates(p))
+            .filter(|p| !disabled.contains(&Self::provider_id(p)))
             .cloned()
             .collect();
         drop(providers_lock); // Release lock immediately
@@ -148,107 +362,708 @@ impl Router {
             anyhow::bail!("No providers available for {} model: {}", kind, model);
         }
 
+        // Step 4: Order by tier, cached provider first within its tier
+        let config = self.config.read().await.clone();
+        let mut candidates = self.order_by_tier(
+            kind,
+            providers,
+            config.strategy,
+            cached_provider_id.as_deref(),
+        );
+        // Route away from keys that are about to run out, keeping them as a last resort
+        candidates.sort_by_key(|p| self.rate_limits.is_low(&Self::provider_id(p)));
+        // Endpoints of other kinds only step in once this kind's own are exhausted
+        candidates.sort_by_key(|p| p.cross_kind);
+
         tracing::debug!(
-            "Using {} cached providers: {:?}",
-            providers.len(),
-            providers
+            "Using {} cached providers ({:?}): {:?}",
+            candidates.len(),
+            config.strategy,
+            candidates
                 .iter()
                 .map(Self::provider_label)
                 .collect::<Vec<_>>()
         );
 
-        // Step 4: Try cached provider first if available
-        if let Some(ref cached_id) = cached_provider_id {
-            if let Some(provider) = providers
-                .iter()
-                .find(|p| Self::provider_id(p) == *cached_id)
-            {
+        // Step 5: Try providers in order, skipping those with an open circuit
+        let mut failed = AllProvidersFailed {
+            model: model.clone(),
+            ..Default::default()
+        };
+        // Last 404 from an auxiliary endpoint, returned if no provider implements it
+        let mut not_found = None;
+        for (idx, provider) in candidates.iter().enumerate() {
+            let provider_id = Self::provider_id(provider);
+            let is_cached = cached_provider_id.as_ref() == Some(&provider_id);
+
+            if let Some(wait) = self.rate_limits.cooldown_remaining(&provider_id) {
                 tracing::debug!(
-                    "Trying cached provider: {} (level {})",
-                    Self::provider_label(provider),
-                    provider.level
+                    "Skipping rate-limited provider for another {}s: {}",
+                    wait.as_secs(),
+                    Self::provider_label(provider)
                 );
-
-                match self.try_provider(provider, endpoint, &body, &headers).await {
-                    Ok(response) => {
-                        self.affinity_manager
-                            .set(&affinity_key, &Self::provider_id(provider))
-                            .await;
-
-                        let duration = start_time.elapsed();
-                        tracing::info!(
-                            "✓ {} {} → {} [cached] {}ms",
-                            kind,
-                            model,
-                            Self::provider_label(provider),
-                            duration.as_millis()
-                        );
-
-                        return Ok(response);
-                    }
-                    Err(e) => {
-                        tracing::warn!(
-                            "✗ Cached provider failed: {} - {}",
-                            Self::provider_label(provider),
-                            e
-                        );
-                        self.affinity_manager.invalidate(&affinity_key).await;
-                    }
-                }
+                failed.record(Self::public_label(provider), "rate-limited");
+                self.metrics
+                    .record_failover(kind, &Self::provider_label(provider), "rate-limited");
+                continue;
             }
-        }
 
-        // Step 5: Try all providers in priority order
-        for (idx, provider) in providers.iter().enumerate() {
-            // Skip if this is the cached provider we already tried
-            if cached_provider_id.as_ref() == Some(&Self::provider_id(provider)) {
+            if !self
+                .circuit_breaker
+                .try_acquire(&provider_id, &config.circuit_breaker)
+            {
+                tracing::debug!(
+                    "Skipping provider with open circuit: {}",
+                    Self::provider_label(provider)
+                );
+                failed.record(Self::public_label(provider), "circuit-open");
+                self.metrics
+                    .record_failover(kind, &Self::provider_label(provider), "circuit-open");
                 continue;
             }
 
             tracing::debug!(
-                "Trying provider: {} (priority #{} level {})",
+                "Trying provider: {} (priority #{} level {}{})",
                 Self::provider_label(provider),
                 idx + 1,
-                provider.level
+                provider.level,
+                if is_cached { ", cached" } else { "" }
             );
 
-            match self.try_provider(provider, endpoint, &body, &headers).await {
-                Ok(response) => {
-                    self.affinity_manager
-                        .set(&affinity_key, &Self::provider_id(provider))
-                        .await;
+            // Rewrite the model name for providers that call it differently
+            let upstream_model = provider.model_map.map(&model);
+            let upstream_body = match &upstream_model {
+                Some(upstream_model) => {
+                    tracing::debug!("Rewriting model {} → {}", model, upstream_model);
+                    rewrite_request_model(&request_json, upstream_model)
+                        .unwrap_or_else(|| body.clone())
+                }
+                None => body.clone(),
+            };
+
+            let timeouts = provider.timeouts.resolve(&config.timeouts);
+            let reason = match self
+                .try_provider(
+                    provider,
+                    method,
+                    endpoint,
+                    &upstream_body,
+                    &headers,
+                    timeouts,
+                )
+                .await
+            {
+                Ok(mut response) => {
+                    self.rate_limits
+                        .observe(&provider_id, response.status(), response.headers());
+                    if upstream_model.is_some() && provider.map_response_model {
+                        response = Self::restore_response_model(response, &model).await;
+                    }
+
+                    self.circuit_breaker.record_success(&provider_id);
+                    // Auxiliary calls neither pin the session nor consume tokens
+                    let response = if auxiliary {
+                        response
+                    } else {
+                        self.affinity_manager.set(&affinity_key, &provider_id).await;
+                        self.track_usage(
+                            response,
+                            provider,
+                            &model,
+                            client,
+                            &affinity_key,
+                            &config.pricing,
+                        )
+                    };
 
                     let duration = start_time.elapsed();
+                    self.metrics.record_request(
+                        kind,
+                        &Self::metric_model(&candidates, &model, Some(provider)),
+                        &Self::provider_label(provider),
+                        "success",
+                        duration,
+                    );
+                    self.metrics
+                        .record_attempt(&Self::provider_label(provider), true);
                     tracing::info!(
-                        "✓ {} {} → {} {}ms",
+                        "✓ {} {} → {}{} {}ms",
                         kind,
                         model,
                         Self::provider_label(provider),
+                        if is_cached { " [cached]" } else { "" },
                         duration.as_millis()
                     );
 
                     return Ok(response);
                 }
+                Err(AttemptError::NonRetryable(response))
+                    if auxiliary && response.status() == StatusCode::NOT_FOUND =>
+                {
+                    // Many relays only implement the model endpoints; ask the next
+                    // provider without counting this against the provider's health
+                    self.circuit_breaker.record_success(&provider_id);
+                    failed.record(Self::public_label(provider), "404");
+                    self.metrics
+                        .record_failover(kind, &Self::provider_label(provider), "404");
+                    tracing::info!(
+                        "{} {} not implemented by {}, trying next provider",
+                        method,
+                        path,
+                        Self::provider_label(provider)
+                    );
+                    not_found = Some(response);
+                    continue;
+                }
+                Err(AttemptError::NonRetryable(response)) => {
+                    self.rate_limits
+                        .observe(&provider_id, response.status(), response.headers());
+                    // The provider is healthy; the request itself was rejected
+                    self.circuit_breaker.record_success(&provider_id);
+                    self.metrics.record_request(
+                        kind,
+                        &Self::metric_model(&candidates, &model, None),
+                        &Self::provider_label(provider),
+                        "non_retryable",
+                        start_time.elapsed(),
+                    );
+                    self.metrics
+                        .record_attempt(&Self::provider_label(provider), true);
+                    tracing::info!(
+                        "✗ {} {} → {} returned non-retryable {}, forwarding to client",
+                        kind,
+                        model,
+                        Self::provider_label(provider),
+                        response.status()
+                    );
+                    return Ok(response);
+                }
+                Err(AttemptError::Retryable(e)) => {
+                    failed.record(Self::public_label(provider), "error");
+                    self.metrics
+                        .record_failover(kind, &Self::provider_label(provider), "error");
+                    e.to_string()
+                }
+                Err(AttemptError::RetryableStatus(upstream)) => {
+                    self.rate_limits
+                        .observe(&provider_id, upstream.status, &upstream.headers);
+                    let reason = upstream.to_string();
+                    self.metrics.record_failover(
+                        kind,
+                        &Self::provider_label(provider),
+                        upstream.status.as_str(),
+                    );
+                    failed.record_upstream(Self::public_label(provider), upstream);
+                    reason
+                }
+            };
+
+            self.circuit_breaker
+                .record_failure(&provider_id, &config.circuit_breaker);
+            self.metrics
+                .record_attempt(&Self::provider_label(provider), false);
+            if is_cached {
+                tracing::warn!(
+                    "✗ Cached provider failed: {} - {}",
+                    Self::provider_label(provider),
+                    reason
+                );
+                self.affinity_manager.invalidate(&affinity_key).await;
+            } else {
+                tracing::warn!(
+                    "✗ Provider failed: {} - {}",
+                    Self::provider_label(provider),
+                    reason
+                );
+            }
+        }
+
+        // Step 6: All providers failed
+        if let Some(response) = not_found {
+            return Ok(response);
+        }
+        self.metrics.record_request(
+            kind,
+            &Self::metric_model(&candidates, &model, None),
+            "none",
+            "failed",
+            start_time.elapsed(),
+        );
+        Err(failed.into())
+    }
+
+    /// Token usage accumulated since the totals were first recorded
+    pub fn usage_snapshot(&self) -> Vec<UsageRecord> {
+        self.usage_stats.snapshot()
+    }
+
+    /// Latest requests with their estimated cost, newest first
+    pub fn recent_requests(&self) -> Vec<RequestCost> {
+        self.usage_stats.recent()
+    }
+
+    pub fn affinity_manager(&self) -> &CacheAffinityManager {
+        &self.affinity_manager
+    }
+
+    /// Per-provider failure ratio over the last few minutes
+    pub fn error_rates(&self) -> Vec<ErrorRate> {
+        self.metrics.error_rates()
+    }
+
+    /// Every resolved endpoint with its runtime switch and circuit state
+    pub async fn provider_statuses(&self) -> Vec<ProviderStatus> {
+        let config = self.config.read().await.clone();
+        let breakers: HashMap<String, BreakerSnapshot> = self
+            .circuit_breaker
+            .snapshot(&config.circuit_breaker)
+            .into_iter()
+            .map(|entry| (entry.provider.clone(), entry))
+            .collect();
+        let disabled = self.disabled.read().await.clone();
+        let providers = self.cached_providers.read().await;
+
+        providers
+            .iter()
+            .map(|provider| {
+                let id = Self::provider_id(provider);
+                let breaker = breakers.get(&id);
+                ProviderStatus {
+                    label: Self::provider_label(provider),
+                    kind: provider.kind.clone(),
+                    name: provider.name.clone(),
+                    api_url: provider.api_url.clone(),
+                    level: provider.level,
+                    weight: provider.weight,
+                    enabled: !disabled.contains(&id),
+                    circuit: breaker.map_or(BreakerState::Closed, |b| b.state),
+                    consecutive_failures: breaker.map_or(0, |b| b.consecutive_failures),
+                    retry_in_secs: breaker.and_then(|b| b.retry_in_secs),
+                    rate_limit: self.rate_limits.snapshot(&id),
+                    protocol: Self::translates(provider).then_some(provider.protocol),
+                    cross_kind: provider.cross_kind,
+                    id,
+                }
+            })
+            .collect()
+    }
+
+    /// Models of every enabled endpoint serving `kinds`, deduplicated by id
+    /// and listed in a shape both the Anthropic and OpenAI SDKs accept
+    pub async fn list_models(&self, kinds: &[&str]) -> Result<Value> {
+        let disabled = self.disabled.read().await.clone();
+        let mut providers: Vec<ResolvedProvider> = self
+            .cached_providers
+            .read()
+            .await
+            .iter()
+            .filter(|p| kinds.contains(&p.kind.as_str()) && !p.cross_kind)
+            .filter(|p| !disabled.contains(&Self::provider_id(p)))
+            .cloned()
+            .collect();
+        providers.sort_by_key(|p| p.level);
+
+        let global = self.config.read().await.timeouts.clone();
+        let results = futures::future::join_all(
+            providers
+                .iter()
+                .map(|provider| self.fetch_models(provider, provider.timeouts.resolve(&global))),
+        )
+        .await;
+
+        let mut seen = HashSet::new();
+        let mut data = Vec::new();
+        let mut errors = Vec::new();
+        for (provider, result) in providers.iter().zip(results) {
+            let models = match result {
+                Ok(models) => models,
                 Err(e) => {
                     tracing::warn!(
-                        "✗ Provider failed: {} - {}",
+                        "Failed to list models of {}: {}",
                         Self::provider_label(provider),
                         e
                     );
+                    errors.push(format!("{}: {}", Self::provider_label(provider), e));
+                    continue;
+                }
+            };
+            for model in models {
+                let Some(id) = model["id"].as_str() else {
+                    continue;
+                };
+                if !provider.model_filter.allows(id) || !seen.insert(id.to_string()) {
+                    continue;
                 }
+                let mut entry = serde_json::json!({
+                    "id": id,
+                    "object": "model",
+                    "type": "model",
+                    "display_name": model["display_name"].as_str().unwrap_or(id),
+                    "owned_by": model["owned_by"]
+                        .as_str()
+                        .or(provider.name.as_deref())
+                        .unwrap_or(&provider.api_url),
+                });
+                for field in ["created", "created_at"] {
+                    if !model[field].is_null() {
+                        entry[field] = model[field].clone();
+                    }
+                }
+                data.push(entry);
             }
         }
 
-        // Step 6: All providers failed
-        anyhow::bail!(
-            "All {} providers failed for model: {}",
-            providers.len(),
-            model
+        if data.is_empty() && !errors.is_empty() {
+            anyhow::bail!("No provider listed its models: {}", errors.join("; "));
+        }
+        Ok(serde_json::json!({
+            "object": "list",
+            "data": data,
+            "has_more": false,
+            "first_id": data.first().map(|m| m["id"].clone()),
+            "last_id": data.last().map(|m| m["id"].clone()),
+        }))
+    }
+
+    /// The upstream `models` list of one endpoint
+    async fn fetch_models(
+        &self,
+        provider: &ResolvedProvider,
+        timeouts: Timeouts,
+    ) -> Result<Vec<Value>> {
+        let path = match provider.protocol {
+            Protocol::AnthropicMessages => "/v1/models?limit=1000",
+            Protocol::OpenaiChat | Protocol::OpenaiResponses => "/models",
+        };
+        let url = format!("{}{}", provider.api_url.trim_end_matches('/'), path);
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        Self::apply_auth(provider, &mut headers)?;
+        if provider.protocol == Protocol::AnthropicMessages
+            && !headers.contains_key("anthropic-version")
+        {
+            headers.insert(
+                "anthropic-version",
+                reqwest::header::HeaderValue::from_static(ANTHROPIC_VERSION),
+            );
+        }
+
+        let mut request = self
+            .http_client(timeouts.connect)?
+            .get(&url)
+            .headers(headers);
+        if let Some(timeout) = timeouts.first_byte {
+            request = request.timeout(timeout);
+        }
+        let response = request.send().await.context("Request failed")?;
+        if !response.status().is_success() {
+            anyhow::bail!("HTTP {}", response.status());
+        }
+        let body = axum::body::to_bytes(
+            Self::into_axum_response(response, None)?.into_body(),
+            MAX_RESPONSE_REWRITE_BYTES,
         )
+        .await
+        .context("Failed to read models list")?;
+        let list: Value = serde_json::from_slice(&body).context("Models list is not JSON")?;
+        Ok(list["data"].as_array().cloned().unwrap_or_default())
+    }
+
+    /// Switch endpoints on or off until restart, without editing provider.json.
+    /// `selector` is a provider id or a provider `name` (all of its endpoints).
+    /// Returns the ids that matched.
+    pub async fn set_provider_enabled(&self, selector: &str, enabled: bool) -> Vec<String> {
+        let ids: Vec<String> = self
+            .cached_providers
+            .read()
+            .await
+            .iter()
+            .filter(|p| Self::provider_id(p) == selector || p.name.as_deref() == Some(selector))
+            .map(Self::provider_id)
+            .collect();
+
+        let mut disabled = self.disabled.write().await;
+        for id in &ids {
+            if enabled {
+                disabled.remove(id);
+            } else {
+                disabled.insert(id.clone());
+            }
+        }
+        tracing::info!(
+            "{} {} provider endpoint(s) matching {}",
+            if enabled { "Enabled" } else { "Disabled" },
+            ids.len(),
+            selector
+        );
+        ids
+    }
+
+    /// All metrics in Prometheus text format
+    pub async fn render_metrics(&self) -> String {
+        let mut out = String::new();
+        self.metrics.render(&mut out);
+
+        let (hits, misses) = self.affinity_manager.lookup_counts();
+        metrics::header(
+            &mut out,
+            "cc_proxy_affinity_lookups_total",
+            "counter",
+            "Cache affinity lookups by result",
+        );
+        metrics::sample(
+            &mut out,
+            "cc_proxy_affinity_lookups_total",
+            &[("result", "hit")],
+            hits as f64,
+        );
+        metrics::sample(
+            &mut out,
+            "cc_proxy_affinity_lookups_total",
+            &[("result", "miss")],
+            misses as f64,
+        );
+
+        // Every configured provider, so closed circuits show up too
+        metrics::header(
+            &mut out,
+            "cc_proxy_circuit_state",
+            "gauge",
+            "Circuit breaker state per provider (1 for the current state)",
+        );
+        for provider in self.provider_statuses().await {
+            for (state, name) in [
+                (BreakerState::Closed, "closed"),
+                (BreakerState::Open, "open"),
+                (BreakerState::HalfOpen, "half-open"),
+            ] {
+                metrics::sample(
+                    &mut out,
+                    "cc_proxy_circuit_state",
+                    &[
+                        ("kind", &provider.kind),
+                        ("provider", &provider.label),
+                        ("state", name),
+                    ],
+                    if state == provider.circuit { 1.0 } else { 0.0 },
+                );
+            }
+        }
+
+        let mut tokens: BTreeMap<(String, String), Usage> = BTreeMap::new();
+        let mut cost: BTreeMap<(String, String), f64> = BTreeMap::new();
+        for record in self.usage_stats.snapshot() {
+            let key = (record.provider.clone(), record.model.clone());
+            tokens.entry(key.clone()).or_default().add(&record.usage);
+            *cost.entry(key).or_default() += record.cost_usd;
+        }
+        metrics::header(
+            &mut out,
+            "cc_proxy_tokens_total",
+            "counter",
+            "Tokens by provider, model and type",
+        );
+        for ((provider, model), usage) in &tokens {
+            for (kind, count) in [
+                ("input", usage.input_tokens),
+                ("output", usage.output_tokens),
+                ("cache_read", usage.cache_read_tokens),
+                ("cache_write", usage.cache_write_tokens),
+            ] {
+                metrics::sample(
+                    &mut out,
+                    "cc_proxy_tokens_total",
+                    &[("provider", provider), ("model", model), ("type", kind)],
+                    count as f64,
+                );
+            }
+        }
+        metrics::header(
+            &mut out,
+            "cc_proxy_cost_usd_total",
+            "counter",
+            "Estimated spend from the pricing table",
+        );
+        for ((provider, model), usd) in &cost {
+            metrics::sample(
+                &mut out,
+                "cc_proxy_cost_usd_total",
+                &[("provider", provider), ("model", model)],
+                *usd,
+            );
+        }
+
+        out
+    }
+
+    /// Periodically save usage totals so they survive restarts
+    pub fn start_usage_persist_task(router: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(tokio::time::Duration::from_secs(5));
+            let mut saved = router.usage_snapshot();
+
+            loop {
+                ticker.tick().await;
+
+                let records = router.usage_snapshot();
+                if records == saved {
+                    continue;
+                }
+                match write_usage_file(&records) {
+                    Ok(()) => saved = records,
+                    Err(e) => tracing::warn!("Failed to write usage totals: {:#}", e),
+                }
+            }
+        });
+    }
+
+    /// Group providers into tiers by ascending `level` and order each tier
+    /// with the configured strategy. Lower tiers are exhausted first; the
+    /// `pinned` (cache-affine) provider leads its own tier only.
+    fn order_by_tier(
+        &self,
+        kind: &str,
+        mut providers: Vec<ResolvedProvider>,
+        strategy: Strategy,
+        pinned: Option<&str>,
+    ) -> Vec<ResolvedProvider> {
+        // Stable sort keeps file order inside each tier
+        providers.sort_by_key(|p| p.level);
+
+        let mut ordered = Vec::with_capacity(providers.len());
+        for tier in providers.chunk_by(|a, b| a.level == b.level) {
+            let tier_key = format!("{}:{}", kind, tier[0].level);
+            let weights: Vec<u32> = tier.iter().map(|p| p.weight).collect();
+            let start = ordered.len();
+            for idx in self.balancer.order(&tier_key, &weights, strategy) {
+                ordered.push(tier[idx].clone());
+            }
+            if let Some(pos) = pinned.and_then(|id| {
+                ordered[start..]
+                    .iter()
+                    .position(|p| Self::provider_id(p) == id)
+            }) {
+                ordered[start..=start + pos].rotate_right(1);
+            }
+        }
+
+        ordered
+    }
+
+    /// Whether `path` generates tokens for `kind` clients, as opposed to an
+    /// auxiliary endpoint that is forwarded without accounting
+    pub fn is_model_endpoint(kind: &str, path: &str) -> bool {
+        path == Protocol::native(kind).endpoint()
+            || (kind == "openai" && matches!(path, "/completions" | "/embeddings"))
     }
 
+    /// Whether requests from `kind` clients can be sent to an endpoint speaking `protocol`
+    fn can_translate(kind: &str, protocol: Protocol) -> bool {
+        protocol == Protocol::native(kind)
+            || matches!(
+                (kind, protocol),
+                ("claude" | "codex", Protocol::OpenaiChat) | ("codex", Protocol::AnthropicMessages)
+            )
+    }
+
+    /// Whether the provider speaks something other than its clients' protocol
+    fn translates(provider: &ResolvedProvider) -> bool {
+        provider.protocol != Protocol::native(&provider.kind)
+    }
+
+    /// Request path and body in the provider's protocol
+    fn upstream_request(
+        provider: &ResolvedProvider,
+        endpoint: &str,
+        body: &Bytes,
+    ) -> Result<(String, Bytes)> {
+        if !Self::translates(provider) {
+            // Reasoning a translated reply produced would be rejected by a native
+            // upstream: Claude thinking after a cross-kind fallback for Codex, and
+            // unsigned thinking from a Chat Completions endpoint for Claude
+            let strip: Option<fn(&mut Value) -> bool> = match provider.kind.as_str() {
+                "codex" if responses_messages::has_claude_reasoning(body) => {
+                    Some(responses_messages::strip_claude_reasoning)
+                }
+                "claude" if messages_chat::has_unsigned_thinking(body) => {
+                    Some(messages_chat::strip_unsigned_thinking)
+                }
+                _ => None,
+            };
+            if let Some(strip) = strip {
+                let mut request: Value =
+                    serde_json::from_slice(body).context("Failed to parse request body as JSON")?;
+                if strip(&mut request) {
+                    return Ok((
+                        endpoint.to_string(),
+                        Bytes::from(serde_json::to_vec(&request)?),
+                    ));
+                }
+            }
+            return Ok((endpoint.to_string(), body.clone()));
+        }
+
+        let request: Value =
+            serde_json::from_slice(body).context("Failed to parse request body as JSON")?;
+        let translated = match (provider.kind.as_str(), provider.protocol) {
+            ("claude", Protocol::OpenaiChat) => messages_chat::translate_request(&request),
+            ("codex", Protocol::OpenaiChat) => responses_chat::translate_request(&request),
+            ("codex", Protocol::AnthropicMessages) => {
+                responses_messages::translate_request(&request)
+            }
+            (kind, protocol) => anyhow::bail!("No translation from {} to {:?}", kind, protocol),
+        };
+        Ok((
+            provider.protocol.endpoint().to_string(),
+            Bytes::from(serde_json::to_vec(&translated)?),
+        ))
+    }
+
+    /// Bring a successful upstream response back into the client's protocol
+    async fn translate_response(
+        provider: &ResolvedProvider,
+        response: Response<Body>,
+        is_event_stream: bool,
+    ) -> Result<Response<Body>> {
+        if !Self::translates(provider) {
+            return Ok(response);
+        }
+
+        let (parts, body) = response.into_parts();
+        let route = (provider.kind.as_str(), provider.protocol);
+        if is_event_stream {
+            let stream = body.into_data_stream();
+            let body = match route {
+                ("claude", _) => Body::from_stream(messages_chat::translate_stream(stream)),
+                (_, Protocol::AnthropicMessages) => {
+                    Body::from_stream(responses_messages::translate_stream(stream))
+                }
+                _ => Body::from_stream(responses_chat::translate_stream(stream)),
+            };
+            return Ok(Response::from_parts(parts, body));
+        }
+
+        let bytes = axum::body::to_bytes(body, MAX_RESPONSE_REWRITE_BYTES)
+            .await
+            .context("Failed to read response for translation")?;
+        let translated = match route {
+            ("claude", _) => messages_chat::translate_response(&bytes),
+            (_, Protocol::AnthropicMessages) => responses_messages::translate_response(&bytes),
+            _ => responses_chat::translate_response(&bytes),
+        };
+        let translated = translated.context("Upstream response could not be translated")?;
+        Ok(Response::from_parts(parts, Body::from(translated)))
+    }
+
+    /// Stable identity for affinity and health tracking. The key fingerprint
+    /// keeps several keys on the same relay apart.
     fn provider_id(provider: &ResolvedProvider) -> String {
-        format!("{}::{}", provider.kind, provider.api_url)
+        format!(
+            "{}::{}::{}",
+            provider.kind,
+            provider.api_url,
+            &hash_string(&provider.api_key)[..8]
+        )
     }
 
     fn provider_label(provider: &ResolvedProvider) -> String {
@@ -259,21 +1074,63 @@ impl Router {
         }
     }
 
+    /// Model name used as a metrics label. The requested name is client input,
+    /// so it only becomes a label once an upstream served it (under its mapped
+    /// name) or a provider names it in `models` / `modelMap`; otherwise `other`.
+    fn metric_model(
+        candidates: &[ResolvedProvider],
+        model: &str,
+        served_by: Option<&ResolvedProvider>,
+    ) -> String {
+        if let Some(provider) = served_by {
+            return provider
+                .model_map
+                .map(model)
+                .unwrap_or_else(|| model.to_string());
+        }
+        let configured = candidates.iter().any(|p| {
+            p.model_map.map(model).is_some()
+                || (!p.model_filter.allow.is_empty() && p.model_filter.allows(model))
+        });
+        if configured {
+            model.to_string()
+        } else {
+            "other".to_string()
+        }
+    }
+
+    /// How a provider is named to clients (e.g. in `x-cc-proxy-attempts`):
+    /// its `name`, or a short hash of its id. Upstream URLs stay in the logs.
+    fn public_label(provider: &ResolvedProvider) -> String {
+        match provider.name.as_ref().filter(|n| !n.is_empty()) {
+            Some(name) => name.clone(),
+            None => format!(
+                "provider-{}",
+                &hash_string(&Self::provider_id(provider))[..8]
+            ),
+        }
+    }
+
     /// Try to forward request to a specific provider
     async fn try_provider(
         &self,
         provider: &ResolvedProvider,
+        method: &Method,
         endpoint: &str,
         body: &Bytes,
         headers: &HeaderMap,
-    ) -> Result<Response<Body>> {
-        // Construct URL
+        timeouts: Timeouts,
+    ) -> Result<Response<Body>, AttemptError> {
+        // Construct URL and body in the provider's protocol
+        let (endpoint, body) = Self::upstream_request(provider, endpoint, body)?;
         let url = format!("{}{}", provider.api_url.trim_end_matches('/'), endpoint);
+        let translates = Self::translates(provider);
 
         // Prepare headers - convert from axum HeaderMap to reqwest HeaderMap
         let mut req_headers = reqwest::header::HeaderMap::new();
         for (key, value) in headers {
-            if key == "host" || key == "authorization" {
+            // Never leak the client's proxy token upstream
+            if key == "host" || key == "authorization" || key == "x-api-key" {
                 continue;
             }
 
@@ -291,6 +1148,9 @@ impl Router {
             if is_hop_by_hop || lower == "content-length" {
                 continue;
             }
+            if translates && lower.starts_with("anthropic-") {
+                continue;
+            }
 
             // Convert header name and value
             if let Ok(req_name) = reqwest::header::HeaderName::from_bytes(key.as_str().as_bytes()) {
@@ -301,10 +1161,16 @@ impl Router {
         }
 
         // Set provider's API key
-        req_headers.insert(
-            reqwest::header::AUTHORIZATION,
-            reqwest::header::HeaderValue::from_str(&format!("Bearer {}", provider.api_key))?,
-        );
+        Self::apply_auth(provider, &mut req_headers)?;
+        if translates
+            && provider.protocol == Protocol::AnthropicMessages
+            && !req_headers.contains_key("anthropic-version")
+        {
+            req_headers.insert(
+                "anthropic-version",
+                reqwest::header::HeaderValue::from_static(ANTHROPIC_VERSION),
+            );
+        }
 
         // Ensure Accept header
         if !req_headers.contains_key(reqwest::header::ACCEPT) {
@@ -314,20 +1180,50 @@ impl Router {
             );
         }
 
-        // Forward request
-        let response = self
-            .http_client
-            .post(&url)
+        // Forward request; the first-byte deadline covers response headers
+        // and, for streams, the wait for the first content event
+        let deadline = timeouts
+            .first_byte
+            .map(|timeout| tokio::time::Instant::now() + timeout);
+        let method = reqwest::Method::from_bytes(method.as_str().as_bytes())
+            .context("Unsupported request method")?;
+        let request = self
+            .http_client(timeouts.connect)?
+            .request(method, &url)
             .headers(req_headers)
             .body(body.to_vec())
-            .send()
-            .await
+            .send();
+        let response = within_deadline(deadline, request)
+            .await?
             .context("Failed to send request to provider")?;
 
         let status = response.status();
 
         if !status.is_success() {
-            anyhow::bail!("Provider returned error status: {}", status);
+            let (parts, body) = Self::into_axum_response(response, None)?.into_parts();
+            let mut body =
+                within_deadline(deadline, axum::body::to_bytes(body, MAX_ERROR_BODY_BYTES))
+                    .await
+                    .ok()
+                    .and_then(|body| body.ok())
+                    .unwrap_or_default();
+            if translates {
+                body =
+                    Bytes::from(native_error_body(&provider.kind, parts.status, &body).to_string());
+            }
+
+            if provider.status_policy.is_retryable(status.as_u16()) {
+                return Err(AttemptError::RetryableStatus(UpstreamFailure {
+                    status: parts.status,
+                    headers: parts.headers,
+                    body,
+                }));
+            }
+
+            return Err(AttemptError::NonRetryable(Response::from_parts(
+                parts,
+                Body::from(body),
+            )));
         }
 
         // Check for WAF/firewall blocks (provider returns 200 but with error content)
@@ -362,8 +1258,248 @@ impl Router {
             }
         }
 
-        // Convert reqwest::Response to axum Response
-        let axum_status = StatusCode::from_u16(status.as_u16())?;
+        let is_event_stream = response
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| ct.contains("text/event-stream"));
+
+        if is_event_stream {
+            let axum_response = Self::into_axum_response(response, None)?;
+            let axum_response = Self::translate_response(provider, axum_response, true).await?;
+            return Self::await_first_content(provider, axum_response, deadline, timeouts).await;
+        }
+        let axum_response = Self::into_axum_response(response, timeouts.stream_idle)?;
+        Ok(Self::translate_response(provider, axum_response, false).await?)
+    }
+
+    /// Map the upstream model name in a response back to the requested one
+    async fn restore_response_model(response: Response<Body>, model: &str) -> Response<Body> {
+        let is_event_stream = response
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| ct.contains("text/event-stream"));
+        let (parts, body) = response.into_parts();
+
+        if is_event_stream {
+            let stream = restore_sse_model(body.into_data_stream(), model.to_string());
+            return Response::from_parts(parts, Body::from_stream(stream));
+        }
+
+        match axum::body::to_bytes(body, MAX_RESPONSE_REWRITE_BYTES).await {
+            Ok(bytes) => {
+                let body = restore_json_model(&bytes, model).unwrap_or(bytes);
+                Response::from_parts(parts, Body::from(body))
+            }
+            Err(e) => {
+                tracing::warn!("Failed to read response for model mapping: {}", e);
+                Response::from_parts(parts, Body::empty())
+            }
+        }
+    }
+
+    /// Account the response's token usage and extend the affinity when the
+    /// provider wrote a longer-lived prompt cache (e.g. Anthropic's 1h TTL)
+    fn track_usage(
+        &self,
+        response: Response<Body>,
+        provider: &ResolvedProvider,
+        model: &str,
+        client: &str,
+        affinity_key: &str,
+        pricing: &[PriceRule],
+    ) -> Response<Body> {
+        let is_event_stream = response
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| ct.contains("text/event-stream"));
+        let (parts, body) = response.into_parts();
+
+        let stats = self.usage_stats.clone();
+        let manager = self.affinity_manager.clone();
+        let inflight = is_event_stream.then(|| self.metrics.stream_started(&provider.kind));
+        let provider_id = Self::provider_id(provider);
+        let label = Self::provider_label(provider);
+        let name = provider.name.clone();
+        let pricing = pricing.to_vec();
+        let (model, client, key) = (
+            model.to_string(),
+            client.to_string(),
+            affinity_key.to_string(),
+        );
+        let stream = observe_usage(body.into_data_stream(), is_event_stream, move |usage| {
+            let cost = estimate_cost(&pricing, name.as_deref(), &model, &usage);
+            tracing::debug!(
+                "Usage {} → {}: in={} out={} cache_read={} cache_write={} cost=${:.4}",
+                model,
+                label,
+                usage.input_tokens,
+                usage.output_tokens,
+                usage.cache_read_tokens,
+                usage.cache_write_tokens,
+                cost.unwrap_or_default().total
+            );
+            stats.record(&label, &model, &client, &usage, cost);
+
+            let Some(ttl) = usage.cache_ttl_secs() else {
+                return;
+            };
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                runtime.spawn(async move { manager.extend(&key, &provider_id, ttl).await });
+            }
+        })
+        .map(move |chunk| {
+            // Held until the client stops reading the stream
+            let _ = &inflight;
+            chunk
+        });
+
+        Response::from_parts(parts, Body::from_stream(stream))
+    }
+
+    /// Get (or build) an HTTP client with the given connect timeout
+    fn http_client(&self, connect_timeout: Option<Duration>) -> Result<reqwest::Client> {
+        let mut clients = self.http_clients.lock().unwrap();
+        if let Some(client) = clients.get(&connect_timeout) {
+            return Ok(client.clone());
+        }
+
+        // Keep provider responses compressed so headers stay consistent end-to-end.
+        let mut builder = reqwest::Client::builder()
+            .no_gzip()
+            .no_deflate()
+            .no_brotli();
+        if let Some(timeout) = connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        let client = builder.build()?;
+        clients.insert(connect_timeout, client.clone());
+        Ok(client)
+    }
+
+    /// Hold back an SSE response until the first content event, so a stream
+    /// that dies or reports an error before any output can still fail over.
+    /// Once content has started the rest of the stream is passed through.
+    async fn await_first_content(
+        provider: &ResolvedProvider,
+        response: Response<Body>,
+        deadline: Option<tokio::time::Instant>,
+        timeouts: Timeouts,
+    ) -> Result<Response<Body>, AttemptError> {
+        let (parts, body) = response.into_parts();
+        let mut stream = body.into_data_stream();
+        let mut parser = SseParser::new();
+        let mut buffered = BytesMut::new();
+
+        loop {
+            let chunk = match within_deadline(deadline, stream.next()).await? {
+                Some(chunk) => chunk.context("Stream failed before first content")?,
+                None => {
+                    return Err(anyhow::anyhow!("Stream ended before first content").into());
+                }
+            };
+            buffered.extend_from_slice(&chunk);
+
+            let mut ready = false;
+            for event in parser.feed(&chunk) {
+                match sse::classify(&event) {
+                    EventClass::Content | EventClass::Terminal => {
+                        ready = true;
+                        break;
+                    }
+                    EventClass::Error(failure)
+                        if provider.status_policy.is_retryable(failure.status.as_u16()) =>
+                    {
+                        return Err(AttemptError::RetryableStatus(failure));
+                    }
+                    EventClass::Error(_) => {
+                        // Request-side error: hand the stream so far to the client as-is
+                        return Err(AttemptError::NonRetryable(Response::from_parts(
+                            parts,
+                            Body::from(buffered.freeze()),
+                        )));
+                    }
+                    EventClass::Other => {}
+                }
+            }
+
+            if ready || buffered.len() > MAX_PREFETCH_BYTES {
+                break;
+            }
+        }
+
+        tracing::debug!(
+            "First content received after {} buffered bytes",
+            buffered.len()
+        );
+        let idle_event = sse::error_event(
+            &provider.kind,
+            if provider.kind == "claude" {
+                "api_error"
+            } else {
+                "server_error"
+            },
+            "Upstream stream idle timeout",
+        );
+        let rest = with_idle_timeout(stream, timeouts.stream_idle, Some(idle_event));
+        let prefix = futures::stream::once(async move { Ok(buffered.freeze()) });
+        Ok(Response::from_parts(
+            parts,
+            Body::from_stream(prefix.chain(rest)),
+        ))
+    }
+
+    /// Inject the provider's credentials according to its auth scheme
+    fn apply_auth(
+        provider: &ResolvedProvider,
+        req_headers: &mut reqwest::header::HeaderMap,
+    ) -> Result<()> {
+        use reqwest::header::{HeaderName, HeaderValue as ReqHeaderValue};
+
+        match provider.auth_scheme {
+            AuthScheme::Bearer => {
+                req_headers.insert(
+                    reqwest::header::AUTHORIZATION,
+                    ReqHeaderValue::from_str(&format!("Bearer {}", provider.api_key))
+                        .context("Invalid API key header value")?,
+                );
+            }
+            AuthScheme::XApiKey => {
+                req_headers.insert(
+                    "x-api-key",
+                    ReqHeaderValue::from_str(&provider.api_key)
+                        .context("Invalid API key header value")?,
+                );
+                if !req_headers.contains_key("anthropic-version") {
+                    req_headers.insert(
+                        "anthropic-version",
+                        ReqHeaderValue::from_static(ANTHROPIC_VERSION),
+                    );
+                }
+            }
+            AuthScheme::None => {}
+            AuthScheme::CustomHeader => {
+                let name = provider.auth_header.as_deref().unwrap_or_default();
+                req_headers.insert(
+                    HeaderName::from_bytes(name.as_bytes()).context("Invalid authHeader name")?,
+                    ReqHeaderValue::from_str(&provider.api_key)
+                        .context("Invalid API key header value")?,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Convert a reqwest::Response to an axum Response, streaming the body
+    /// and transparently decompressing gzip
+    fn into_axum_response(
+        response: reqwest::Response,
+        idle_timeout: Option<Duration>,
+    ) -> Result<Response<Body>> {
+        let axum_status = StatusCode::from_u16(response.status().as_u16())?;
         let mut axum_response = Response::builder().status(axum_status);
 
         // Copy headers - convert from reqwest to axum
@@ -406,18 +1542,19 @@ impl Router {
         }
 
         // Stream the response body directly without buffering
-        let stream = response.bytes_stream().map_err(|e| {
-            std::io::Error::new(std::io::ErrorKind::Other, e)
-        });
+        let stream = Box::pin(with_idle_timeout(
+            response.bytes_stream(),
+            idle_timeout,
+            None,
+        ));
 
         let body = if has_gzip_encoding {
             // Decompress gzipped response
             tracing::debug!("Decompressing gzipped response");
             let reader = StreamReader::new(stream);
             let decoder = GzipDecoder::new(reader);
-            let decompressed_stream = tokio_util::io::ReaderStream::new(decoder).map_err(|e| {
-                std::io::Error::new(std::io::ErrorKind::Other, e)
-            });
+            let decompressed_stream =
+                tokio_util::io::ReaderStream::new(decoder).map_err(std::io::Error::other);
             Body::from_stream(decompressed_stream)
         } else {
             // Pass through uncompressed
@@ -427,7 +1564,10 @@ impl Router {
                     let preview = &chunk[..chunk.len().min(50)];
                     match std::str::from_utf8(preview) {
                         Ok(s) => tracing::debug!("Response chunk (UTF-8): {:?}...", s),
-                        Err(_) => tracing::debug!("Response chunk (bytes): {:02x?}...", &preview[..preview.len().min(20)]),
+                        Err(_) => tracing::debug!(
+                            "Response chunk (bytes): {:02x?}...",
+                            &preview[..preview.len().min(20)]
+                        ),
                     }
                 }
             }))
@@ -435,28 +1575,260 @@ impl Router {
 
         axum_response.body(body).context("Failed to build response")
     }
+}
+
+/// Await `future` until `deadline`; a missed deadline is a retryable failure
+async fn within_deadline<F: std::future::Future>(
+    deadline: Option<tokio::time::Instant>,
+    future: F,
+) -> Result<F::Output> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, future)
+            .await
+            .map_err(|_| anyhow::anyhow!("First-byte timeout exceeded")),
+        None => Ok(future.await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::ProviderConfig;
+
+    fn resolve(json: &str) -> Vec<ResolvedProvider> {
+        let config: ProviderConfig = serde_json::from_str(json).unwrap();
+        let ProviderConfig::List { providers } = config else {
+            panic!("expected list config");
+        };
+        Router::flatten_providers(providers)
+    }
 
-    /// Extract user ID from Authorization header (hash of API key)
-    fn extract_user_id(&self, headers: &HeaderMap) -> String {
-        if let Some(auth) = headers.get("authorization") {
-            if let Ok(auth_str) = auth.to_str() {
-                let token = auth_str.strip_prefix("Bearer ").unwrap_or(auth_str).trim();
-                return hash_string(token);
+    fn router() -> Router {
+        Router {
+            affinity_manager: Arc::new(CacheAffinityManager::new(300)),
+            http_clients: Arc::new(Mutex::new(HashMap::new())),
+            cached_providers: Arc::new(RwLock::new(Vec::new())),
+            config: Arc::new(RwLock::new(ProxyConfig::default())),
+            balancer: Arc::new(Balancer::new()),
+            circuit_breaker: Arc::new(CircuitBreaker::new()),
+            usage_stats: Arc::new(UsageStats::new()),
+            metrics: Arc::new(Metrics::new()),
+            disabled: Arc::new(RwLock::new(HashSet::new())),
+            quotas: Arc::new(QuotaTracker::new()),
+            rate_limits: Arc::new(RateLimitTracker::new()),
+        }
+    }
+
+    #[test]
+    fn resolved_providers_apply_filters_maps_and_status_overrides() {
+        let providers = resolve(
+            r#"
+            {
+                "providers": [
+                    {
+                        "claude": { "apiUrl": "https://cheap.api", "apiKey": "k1" },
+                        "retryableStatuses": [400],
+                        "nonRetryableStatuses": [429],
+                        "models": ["claude-haiku-*", "claude-sonnet-*"],
+                        "excludeModels": ["claude-sonnet-3*"],
+                        "modelMap": { "claude-sonnet-*": "sonnet-*" }
+                    }
+                ]
             }
+            "#,
+        );
+
+        let cheap = &providers[0];
+        assert!(cheap.model_filter.allows("claude-haiku-4-5"));
+        assert!(cheap.model_filter.allows("claude-sonnet-4-5"));
+        assert!(!cheap.model_filter.allows("claude-sonnet-3-7"));
+        assert!(!cheap.model_filter.allows("claude-opus-4-1"));
+        assert_eq!(
+            cheap.model_map.map("claude-sonnet-4-5").as_deref(),
+            Some("sonnet-4-5")
+        );
+        assert!(cheap.status_policy.is_retryable(400));
+        assert!(!cheap.status_policy.is_retryable(429));
+        assert!(cheap.status_policy.is_retryable(503));
+    }
+
+    #[test]
+    fn resolved_providers_check_auth_scheme_requirements() {
+        let providers = resolve(
+            r#"
+            {
+                "providers": [
+                    { "claude": { "apiUrl": "http://localhost:8080", "authScheme": "none" } },
+                    { "claude": { "apiUrl": "https://keyless.api" } },
+                    { "claude": { "apiUrl": "https://azure.api", "apiKey": "k", "authScheme": "custom-header" } }
+                ]
+            }
+            "#,
+        );
+
+        let urls: Vec<&str> = providers.iter().map(|p| p.api_url.as_str()).collect();
+        assert_eq!(urls, vec!["http://localhost:8080"]);
+    }
+
+    #[test]
+    fn apply_auth_follows_auth_scheme() {
+        let mut provider = resolve(
+            r#"
+            {
+                "providers": [
+                    { "claude": { "apiUrl": "https://api.anthropic.com", "apiKey": "secret", "authScheme": "x-api-key" } }
+                ]
+            }
+            "#,
+        )
+        .remove(0);
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        Router::apply_auth(&provider, &mut headers).unwrap();
+        assert_eq!(headers["x-api-key"], "secret");
+        assert_eq!(headers["anthropic-version"], ANTHROPIC_VERSION);
+        assert!(!headers.contains_key("authorization"));
+
+        provider.auth_scheme = AuthScheme::CustomHeader;
+        provider.auth_header = Some("api-key".to_string());
+        let mut headers = reqwest::header::HeaderMap::new();
+        Router::apply_auth(&provider, &mut headers).unwrap();
+        assert_eq!(headers["api-key"], "secret");
+        assert_eq!(headers.len(), 1);
+
+        provider.auth_scheme = AuthScheme::None;
+        let mut headers = reqwest::header::HeaderMap::new();
+        Router::apply_auth(&provider, &mut headers).unwrap();
+        assert!(headers.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_response_succeeds_under_default_timeouts() {
+        let provider = resolve(
+            r#"{ "providers": [ { "claude": { "apiUrl": "https://slow.api", "apiKey": "k" } } ] }"#,
+        )
+        .remove(0);
+        let timeouts = provider.timeouts.resolve(&TimeoutConfig::default());
+        let deadline = timeouts
+            .first_byte
+            .map(|timeout| tokio::time::Instant::now() + timeout);
+
+        let start = Bytes::from("event: message_start\ndata: {\"type\":\"message_start\"}\n\n");
+        let delta = Bytes::from(
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"hi\"}}\n\n",
+        );
+        let body = futures::stream::iter([Ok::<_, std::io::Error>(start)]).chain(
+            futures::stream::once(async move {
+                tokio::time::sleep(Duration::from_secs(600)).await;
+                Ok(delta)
+            }),
+        );
+
+        let headers = within_deadline(deadline, async {
+            tokio::time::sleep(Duration::from_secs(600)).await;
+            Response::new(Body::from_stream(body))
+        });
+        let response = headers.await.expect("headers within deadline");
+        assert!(
+            Router::await_first_content(&provider, response, deadline, timeouts)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn lower_levels_are_tried_first_and_weights_apply_within_a_tier() {
+        let providers = resolve(
+            r#"
+            {
+                "providers": [
+                    { "name": "backup", "level": 1, "claude": { "apiUrl": "https://backup.api", "apiKey": "k" } },
+                    { "name": "idle", "weight": 0, "claude": { "apiUrl": "https://idle.api", "apiKey": "k" } },
+                    { "name": "main", "weight": 5, "claude": { "apiUrl": "https://main.api", "apiKey": "k" } }
+                ]
+            }
+            "#,
+        );
+
+        let router = router();
+        for _ in 0..20 {
+            let ordered =
+                router.order_by_tier("claude", providers.clone(), Strategy::Weighted, None);
+            let names: Vec<&str> = ordered.iter().filter_map(|p| p.name.as_deref()).collect();
+            assert_eq!(names, vec!["main", "idle", "backup"]);
         }
-        "anonymous".to_string()
     }
-}
 
-/// Create error response
-pub fn error_response(status: StatusCode, message: &str) -> Response<Body> {
-    let error_json = serde_json::json!({
-        "error": message
-    });
-
-    Response::builder()
-        .status(status)
-        .header("content-type", "application/json")
-        .body(Body::from(error_json.to_string()))
-        .unwrap()
+    #[test]
+    fn metric_model_limits_labels_to_configured_models() {
+        let providers = resolve(
+            r#"
+            {
+                "providers": [
+                    {
+                        "claude": { "apiUrl": "https://relay.api", "apiKey": "k" },
+                        "modelMap": { "claude-sonnet-*": "sonnet-*" }
+                    },
+                    { "claude": { "apiUrl": "https://open.api", "apiKey": "k" } }
+                ]
+            }
+            "#,
+        );
+
+        let label = |model, served_by| Router::metric_model(&providers, model, served_by);
+        assert_eq!(
+            label("claude-sonnet-4-5", Some(&providers[0])),
+            "sonnet-4-5"
+        );
+        assert_eq!(
+            label("claude-haiku-4-5", Some(&providers[1])),
+            "claude-haiku-4-5"
+        );
+        assert_eq!(label("claude-sonnet-4-5", None), "claude-sonnet-4-5");
+        assert_eq!(label("made-up-model-123", None), "other");
+    }
+
+    #[test]
+    fn public_label_hides_upstream_url() {
+        let providers = resolve(
+            r#"
+            {
+                "providers": [
+                    { "name": "relay", "claude": { "apiUrl": "https://relay.internal", "apiKey": "k" } },
+                    { "claude": { "apiUrl": "https://secret.internal", "apiKey": "k" } }
+                ]
+            }
+            "#,
+        );
+
+        assert_eq!(Router::public_label(&providers[0]), "relay");
+        let unnamed = Router::public_label(&providers[1]);
+        assert!(unnamed.starts_with("provider-"));
+        assert!(!unnamed.contains("internal"));
+    }
+
+    #[test]
+    fn pinned_provider_leads_only_its_own_tier() {
+        let providers = resolve(
+            r#"
+            {
+                "providers": [
+                    { "name": "main", "claude": { "apiUrl": "https://main.api", "apiKey": "k" } },
+                    { "name": "backup-a", "level": 1, "claude": { "apiUrl": "https://a.api", "apiKey": "k" } },
+                    { "name": "backup-b", "level": 1, "claude": { "apiUrl": "https://b.api", "apiKey": "k" } }
+                ]
+            }
+            "#,
+        );
+        let pinned = Router::provider_id(&providers[2]);
+
+        let ordered = router().order_by_tier(
+            "claude",
+            providers,
+            Strategy::Ordered,
+            Some(pinned.as_str()),
+        );
+        let names: Vec<&str> = ordered.iter().filter_map(|p| p.name.as_deref()).collect();
+        assert_eq!(names, vec!["main", "backup-b", "backup-a"]);
+    }
 }
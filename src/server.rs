@@ -1,11 +1,15 @@
-use crate::router::{error_response, Router};
+use crate::router::Router;
+use crate::upstream_error::{self, native_error_response};
+use crate::usage::UsageSummary;
 use axum::{
     body::Body,
     extract::{Request, State},
-    http::{Response, StatusCode},
-    routing::post,
-    Router as AxumRouter,
+    http::{HeaderMap, Response, StatusCode},
+    routing::{get, post},
+    Json, Router as AxumRouter,
 };
+use serde::Deserialize;
+use serde_json::Value;
 use std::sync::Arc;
 use tower_http::trace::TraceLayer;
 
@@ -22,6 +26,14 @@ pub fn create_app(router: Arc<Router>) -> AxumRouter {
     AxumRouter::new()
         .route("/v1/messages", post(handle_claude))
         .route("/responses", post(handle_codex))
+        .route("/v1/chat/completions", post(handle_openai))
+        .route("/v1/completions", post(handle_openai))
+        .route("/v1/embeddings", post(handle_openai))
+        .route("/v1/models", get(handle_models))
+        .route("/models", get(handle_codex_models))
+        .route("/usage", get(handle_usage))
+        .route("/metrics", get(handle_metrics))
+        .fallback(handle_auxiliary)
         .layer(TraceLayer::new_for_http())
         .with_state(state)
 }
@@ -42,6 +54,147 @@ async fn handle_codex(
     handle_request(state, request, "codex", "/responses").await
 }
 
+/// Handle generic OpenAI-compatible clients (Aider, Continue, scripts, ...).
+/// Their providers' `apiUrl` includes the `/v1`, as for Codex.
+async fn handle_openai(
+    State(state): State<AppState>,
+    request: Request,
+) -> Result<Response<Body>, Response<Body>> {
+    let uri = request
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_default();
+    let endpoint = uri.strip_prefix("/v1").unwrap_or(&uri);
+    handle_request(state, request, "openai", endpoint).await
+}
+
+/// Known auxiliary endpoints of each API, as (kind, path). The path and
+/// anything below it are forwarded; Codex's base URL already includes `/v1`.
+const AUXILIARY_ROUTES: &[(&str, &str)] = &[
+    // Anthropic: count_tokens and batches, model details, files
+    ("claude", "/v1/messages"),
+    ("claude", "/v1/models"),
+    ("claude", "/v1/files"),
+    // OpenAI-compatible clients
+    ("openai", "/v1/moderations"),
+    // Codex: stored responses and compaction, model details
+    ("codex", "/responses"),
+    ("codex", "/models"),
+];
+
+/// Forward other known endpoints of each API (count_tokens, batches, ...)
+/// to that kind's providers; any other path is a 404 that never reaches them
+async fn handle_auxiliary(
+    State(state): State<AppState>,
+    request: Request,
+) -> Result<Response<Body>, Response<Body>> {
+    let path = request.uri().path();
+    let Some(&(kind, _)) = AUXILIARY_ROUTES.iter().find(|(_, prefix)| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }) else {
+        tracing::debug!("No route for {} {}", request.method(), path);
+        let kind = if path.starts_with("/v1/") {
+            "claude"
+        } else {
+            "codex"
+        };
+        return Err(native_error_response(
+            kind,
+            StatusCode::NOT_FOUND,
+            &format!("Unknown endpoint: {}", path),
+        ));
+    };
+
+    let uri = request
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_default();
+    // Like the chat routes, `openai` providers' `apiUrl` includes the `/v1`
+    let endpoint = match kind {
+        "openai" => uri.strip_prefix("/v1").unwrap_or(&uri),
+        _ => &uri,
+    };
+    handle_request(state, request, kind, endpoint).await
+}
+
+/// Models of every provider, for Anthropic and OpenAI clients alike
+async fn handle_models(
+    State(state): State<AppState>,
+    request: Request,
+) -> Result<Json<Value>, Response<Body>> {
+    list_models(state, request, "claude", &["claude", "codex", "openai"]).await
+}
+
+/// Models of the Codex providers (`GET /models` under Codex's base URL)
+async fn handle_codex_models(
+    State(state): State<AppState>,
+    request: Request,
+) -> Result<Json<Value>, Response<Body>> {
+    list_models(state, request, "codex", &["codex"]).await
+}
+
+async fn list_models(
+    state: AppState,
+    request: Request,
+    kind: &str,
+    kinds: &[&str],
+) -> Result<Json<Value>, Response<Body>> {
+    require_client(&state, request.headers(), kind).await?;
+    state
+        .router
+        .list_models(kinds)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!("Listing models failed: {}", e);
+            native_error_response(kind, StatusCode::BAD_GATEWAY, &e.to_string())
+        })
+}
+
+/// Token usage and estimated spend. Client names and spend are only shown
+/// to configured clients.
+async fn handle_usage(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<UsageSummary>, Response<Body>> {
+    require_client(&state, &headers, "codex").await?;
+    let mut summary = UsageSummary::new(state.router.usage_snapshot());
+    summary.recent_requests = state.router.recent_requests();
+    Ok(Json(summary))
+}
+
+/// Prometheus scrape endpoint; labels include provider URLs, so scrapers
+/// present a client token too
+async fn handle_metrics(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, Response<Body>> {
+    require_client(&state, &headers, "codex").await?;
+    Ok(Response::builder()
+        .header("content-type", "text/plain; version=0.0.4")
+        .body(Body::from(state.router.render_metrics().await))
+        .unwrap())
+}
+
+/// Reject a request without a configured client token, in `kind`'s error format
+async fn require_client(
+    state: &AppState,
+    headers: &HeaderMap,
+    kind: &str,
+) -> Result<(), Response<Body>> {
+    if state.router.authenticate(headers).await.is_none() {
+        return Err(native_error_response(
+            kind,
+            StatusCode::UNAUTHORIZED,
+            "Invalid or missing client token",
+        ));
+    }
+    Ok(())
+}
+
 /// Generic request handler
 async fn handle_request(
     state: AppState,
@@ -51,36 +204,77 @@ async fn handle_request(
 ) -> Result<Response<Body>, Response<Body>> {
     // Extract headers
     let headers = request.headers().clone();
+    let method = request.method().clone();
+
+    // Only configured clients may spend the provider keys
+    let Some(client) = state.router.authenticate(&headers).await else {
+        tracing::warn!("Rejected {} request with an unknown client token", kind);
+        return Err(native_error_response(
+            kind,
+            StatusCode::UNAUTHORIZED,
+            "Invalid or missing client token",
+        ));
+    };
 
     // Read body
     let body = match axum::body::to_bytes(request.into_body(), MAX_BODY_BYTES).await {
         Ok(bytes) => bytes,
         Err(e) => {
             tracing::warn!("Request body rejected: {}", e);
-            return Err(error_response(
+            return Err(native_error_response(
+                kind,
                 StatusCode::PAYLOAD_TOO_LARGE,
                 "Request body too large",
             ));
         }
     };
 
+    // Keep one client from draining the shared keys. Auxiliary endpoints
+    // (count_tokens, models, ...) generate no tokens and are not limited.
+    let path = endpoint.split('?').next().unwrap_or_default();
+    let quota = Router::is_model_endpoint(kind, path)
+        .then(|| state.router.acquire_quota(&client, requests_stream(&body)))
+        .transpose();
+    let permit = match quota {
+        Ok(permit) => permit,
+        Err(exceeded) => {
+            tracing::warn!("Client {} over quota: {}", client.name, exceeded.message);
+            let mut response =
+                native_error_response(kind, StatusCode::TOO_MANY_REQUESTS, &exceeded.message);
+            response
+                .headers_mut()
+                .insert("retry-after", exceeded.retry_after_secs.into());
+            return Err(response);
+        }
+    };
+
     // Route request
     match state
         .router
-        .route_request(kind, endpoint, body, headers)
+        .route_request(kind, &method, endpoint, body, headers, &client.name)
         .await
     {
-        Ok(response) => Ok(response),
+        Ok(response) => Ok(match permit {
+            Some(permit) => permit.hold_until_done(response),
+            None => response,
+        }),
         Err(e) => {
             tracing::error!("Request routing failed: {}", e);
-            Err(error_response(
-                StatusCode::BAD_GATEWAY,
-                &format!("All providers failed: {}", e),
-            ))
+            Err(upstream_error::into_response(kind, &e))
         }
     }
 }
 
+/// Whether the request asks for a streamed response (`"stream": true`)
+fn requests_stream(body: &[u8]) -> bool {
+    #[derive(Deserialize)]
+    struct StreamFlag {
+        #[serde(default)]
+        stream: bool,
+    }
+    serde_json::from_slice::<StreamFlag>(body).is_ok_and(|request| request.stream)
+}
+
 pub async fn run_server(router: Arc<Router>, bind_addr: &str) -> anyhow::Result<()> {
     let app = create_app(router);
 
@@ -91,6 +285,11 @@ pub async fn run_server(router: Arc<Router>, bind_addr: &str) -> anyhow::Result<
     tracing::info!("🚀 cc-proxy listening on {}", bind_addr);
     tracing::info!("   POST /v1/messages (Claude Code)");
     tracing::info!("   POST /responses (Codex)");
+    tracing::info!(
+        "   POST /v1/chat/completions, /v1/completions, /v1/embeddings (OpenAI-compatible)"
+    );
+    tracing::info!("   GET  /v1/models, /models (all providers' models)");
+    tracing::info!("   *    other API paths (passthrough)");
 
     axum::serve(listener, app)
         .await
@@ -4,8 +4,8 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use toml::value::Table as TomlTable;
 
-/// Configure Claude Code to use the proxy
-pub fn configure_claude(proxy_addr: &str) -> Result<()> {
+/// Configure Claude Code to use the proxy with the given client token
+pub fn configure_claude(proxy_addr: &str, token: &str) -> Result<()> {
     let home = std::env::var("HOME").context("HOME environment variable not set")?;
     let settings_path = PathBuf::from(home).join(".claude").join("settings.json");
 
@@ -28,7 +28,7 @@ pub fn configure_claude(proxy_addr: &str) -> Result<()> {
 
     env_map.insert(
         "ANTHROPIC_AUTH_TOKEN".into(),
-        JsonValue::String("cc-proxy".into()),
+        JsonValue::String(token.into()),
     );
     env_map.insert(
         "ANTHROPIC_BASE_URL".into(),
@@ -47,8 +47,8 @@ pub fn configure_claude(proxy_addr: &str) -> Result<()> {
     Ok(())
 }
 
-/// Configure Codex to use the proxy
-pub fn configure_codex(proxy_addr: &str) -> Result<()> {
+/// Configure Codex to use the proxy with the given client token
+pub fn configure_codex(proxy_addr: &str, token: &str) -> Result<()> {
     let home = std::env::var("HOME").context("HOME environment variable not set")?;
     let codex_dir = PathBuf::from(home).join(".codex");
 
@@ -99,10 +99,7 @@ pub fn configure_codex(proxy_addr: &str) -> Result<()> {
     // Write auth.json
     let auth_path = codex_dir.join("auth.json");
     let mut auth = load_json_object(&auth_path, "Codex auth.json")?;
-    auth.insert(
-        "OPENAI_API_KEY".into(),
-        JsonValue::String("cc-proxy".into()),
-    );
+    auth.insert("OPENAI_API_KEY".into(), JsonValue::String(token.into()));
     fs::write(
         &auth_path,
         serde_json::to_string_pretty(&JsonValue::Object(auth))?,
@@ -114,9 +111,9 @@ pub fn configure_codex(proxy_addr: &str) -> Result<()> {
 }
 
 /// Configure both Claude Code and Codex
-pub fn configure_all(proxy_addr: &str) -> Result<()> {
-    configure_claude(proxy_addr)?;
-    configure_codex(proxy_addr)?;
+pub fn configure_all(proxy_addr: &str, token: &str) -> Result<()> {
+    configure_claude(proxy_addr, token)?;
+    configure_codex(proxy_addr, token)?;
     tracing::info!("✓ All CLI tools configured to use proxy at {}", proxy_addr);
     Ok(())
 }
@@ -0,0 +1,335 @@
+use crate::upstream_error::UpstreamFailure;
+use axum::http::{HeaderMap, StatusCode};
+use bytes::Bytes;
+use serde_json::{json, Value};
+
+/// A single server-sent event
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+impl SseEvent {
+    /// Event type from the `event:` line, falling back to the JSON `type` field
+    pub fn event_type(&self) -> Option<String> {
+        if let Some(event) = &self.event {
+            return Some(event.clone());
+        }
+        self.json()
+            .and_then(|v| v["type"].as_str().map(str::to_string))
+    }
+
+    pub fn json(&self) -> Option<Value> {
+        serde_json::from_str(&self.data).ok()
+    }
+}
+
+/// Incremental SSE parser; feed raw chunks, get complete events back
+#[derive(Default)]
+pub struct SseParser {
+    // Raw bytes so multi-byte characters split across chunks stay intact
+    buffer: Vec<u8>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.feed_raw(chunk)
+            .into_iter()
+            .filter_map(|(event, _)| event)
+            .collect()
+    }
+
+    /// Like `feed`, but also returns the raw bytes of every complete block
+    /// (separator included), including comment-only blocks that hold no event
+    pub fn feed_raw(&mut self, chunk: &[u8]) -> Vec<(Option<SseEvent>, Vec<u8>)> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut blocks = Vec::new();
+        while let Some((end, sep_len)) = find_event_end(&self.buffer) {
+            let raw: Vec<u8> = self.buffer.drain(..end + sep_len).collect();
+            let event = parse_event(&String::from_utf8_lossy(&raw[..end]));
+            blocks.push((event, raw));
+        }
+        blocks
+    }
+
+    /// Bytes of an unfinished event, once the stream has ended
+    pub fn take_remainder(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+fn find_event_end(buffer: &[u8]) -> Option<(usize, usize)> {
+    let find = |needle: &[u8]| buffer.windows(needle.len()).position(|w| w == needle);
+    let lf = find(b"\n\n").map(|i| (i, 2));
+    let crlf = find(b"\r\n\r\n").map(|i| (i, 4));
+    match (lf, crlf) {
+        (Some(a), Some(b)) => Some(if a.0 <= b.0 { a } else { b }),
+        (a, b) => a.or(b),
+    }
+}
+
+fn parse_event(raw: &str) -> Option<SseEvent> {
+    let mut event = SseEvent::default();
+    let mut data_lines = Vec::new();
+
+    for line in raw.lines() {
+        if line.starts_with(':') {
+            continue; // comment / keep-alive
+        }
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => event.event = Some(value.to_string()),
+            "data" => data_lines.push(value),
+            _ => {}
+        }
+    }
+
+    if event.event.is_none() && data_lines.is_empty() {
+        return None;
+    }
+    event.data = data_lines.join("\n");
+    Some(event)
+}
+
+/// What an event tells us about whether the stream is usable
+#[derive(Debug)]
+pub enum EventClass {
+    /// Generated content has started; the stream can be committed to the client
+    Content,
+    /// The response finished normally
+    Terminal,
+    /// Upstream reported an error in-stream
+    Error(UpstreamFailure),
+    Other,
+}
+
+/// Classify Anthropic Messages, OpenAI Responses and Chat Completions stream events
+pub fn classify(event: &SseEvent) -> EventClass {
+    if event.data.trim() == "[DONE]" {
+        return EventClass::Terminal;
+    }
+
+    let event_type = event.event_type().unwrap_or_default();
+    match event_type.as_str() {
+        "error" | "response.failed" | "response.error" => EventClass::Error(error_failure(event)),
+        "content_block_delta" => EventClass::Content,
+        // Responses API: output_text, function_call_arguments, reasoning_summary_text, ...
+        t if t.starts_with("response.") && t.ends_with(".delta") => EventClass::Content,
+        "message_stop" | "response.completed" | "response.incomplete" => EventClass::Terminal,
+        // Chat Completions chunks carry no type
+        "" => classify_chat_chunk(event),
+        _ => EventClass::Other,
+    }
+}
+
+/// A Chat Completions (or legacy Completions) chunk is content once a choice
+/// carries output; an error arrives as a bare `{"error":{...}}`
+fn classify_chat_chunk(event: &SseEvent) -> EventClass {
+    let Some(chunk) = event.json() else {
+        return EventClass::Other;
+    };
+    if chunk["error"].is_object() {
+        return EventClass::Error(error_failure(event));
+    }
+
+    let has_output = |choice: &Value| {
+        let delta = &choice["delta"];
+        ["content", "reasoning_content", "refusal"]
+            .iter()
+            .any(|field| delta[field].as_str().is_some_and(|s| !s.is_empty()))
+            || delta["tool_calls"].is_array()
+            || choice["text"].as_str().is_some_and(|s| !s.is_empty())
+            || !choice["finish_reason"].is_null()
+    };
+    match chunk["choices"].as_array() {
+        Some(choices) if choices.iter().any(has_output) => EventClass::Content,
+        _ => EventClass::Other,
+    }
+}
+
+/// Build a terminal error event in the stream format of the calling API
+pub fn error_event(kind: &str, error_type: &str, message: &str) -> Bytes {
+    let (event, data) = match kind {
+        "claude" => (
+            "error",
+            json!({ "type": "error", "error": { "type": error_type, "message": message } }),
+        ),
+        // Chat Completions streams have no event names
+        "openai" => {
+            let data = json!({
+                "error": { "message": message, "type": error_type, "param": null, "code": null }
+            });
+            return Bytes::from(format!("data: {}\n\n", data));
+        }
+        _ => (
+            "response.failed",
+            json!({
+                "type": "response.failed",
+                "response": {
+                    "status": "failed",
+                    "error": { "code": error_type, "message": message }
+                }
+            }),
+        ),
+    };
+    Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+/// Turn an in-stream error event into an upstream failure with a matching HTTP status
+fn error_failure(event: &SseEvent) -> UpstreamFailure {
+    let value = event.json().unwrap_or(Value::Null);
+    // Anthropic: {"type":"error","error":{...}}; Responses: {"response":{"error":{...}}}
+    let error = [&value["error"], &value["response"]["error"]]
+        .into_iter()
+        .find(|v| v.is_object())
+        .cloned()
+        .unwrap_or_else(|| json!({ "type": "api_error", "message": event.data }));
+
+    let error_type = error["type"]
+        .as_str()
+        .or_else(|| error["code"].as_str())
+        .unwrap_or_default();
+    let status = match error_type {
+        "overloaded_error" | "server_is_overloaded" => 529,
+        "rate_limit_error" | "rate_limit_exceeded" => 429,
+        "invalid_request_error" => 400,
+        "authentication_error" => 401,
+        "permission_error" => 403,
+        "api_error" | "server_error" => 500,
+        _ => 502,
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert("content-type", "application/json".parse().unwrap());
+
+    UpstreamFailure {
+        status: StatusCode::from_u16(status).unwrap(),
+        headers,
+        body: Bytes::from(json!({ "type": "error", "error": error }).to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_events_across_chunks() {
+        let mut parser = SseParser::new();
+
+        assert!(parser
+            .feed(b"event: message_start\ndata: {\"type\":")
+            .is_empty());
+        let events = parser.feed(b"\"message_start\"}\n\nevent: ping\ndata: {}\n\n");
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event.as_deref(), Some("message_start"));
+        assert_eq!(events[0].data, "{\"type\":\"message_start\"}");
+        assert_eq!(events[1].event_type().as_deref(), Some("ping"));
+    }
+
+    #[test]
+    fn keeps_multibyte_characters_split_across_chunks() {
+        let mut parser = SseParser::new();
+        let data = "data: {\"text\":\"你好\"}\n\n".as_bytes();
+        let split = data.iter().position(|&b| b >= 0x80).unwrap() + 1;
+
+        assert!(parser.feed(&data[..split]).is_empty());
+        let events = parser.feed(&data[split..]);
+        assert_eq!(events[0].json().unwrap()["text"], "你好");
+    }
+
+    #[test]
+    fn parses_crlf_and_comments() {
+        let mut parser = SseParser::new();
+        let events =
+            parser.feed(b": keep-alive\r\n\r\ndata: {\"type\":\"response.created\"}\r\n\r\n");
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type().as_deref(), Some("response.created"));
+    }
+
+    #[test]
+    fn classifies_content_and_terminal_events() {
+        let delta = SseEvent {
+            event: Some("content_block_delta".into()),
+            data: "{}".into(),
+        };
+        let text = SseEvent {
+            event: None,
+            data: r#"{"type":"response.output_text.delta","delta":"hi"}"#.into(),
+        };
+        let done = SseEvent {
+            event: None,
+            data: "[DONE]".into(),
+        };
+
+        assert!(matches!(classify(&delta), EventClass::Content));
+        assert!(matches!(classify(&text), EventClass::Content));
+        assert!(matches!(classify(&done), EventClass::Terminal));
+    }
+
+    #[test]
+    fn classifies_overloaded_error() {
+        let event = SseEvent {
+            event: Some("error".into()),
+            data: r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#
+                .into(),
+        };
+
+        let EventClass::Error(failure) = classify(&event) else {
+            panic!("expected error");
+        };
+        assert_eq!(failure.status.as_u16(), 529);
+        let body: Value = serde_json::from_slice(&failure.body).unwrap();
+        assert_eq!(body["error"]["message"], "Overloaded");
+    }
+
+    #[test]
+    fn error_event_round_trips_through_classifier() {
+        for kind in ["claude", "codex", "openai"] {
+            let mut parser = SseParser::new();
+            let events = parser.feed(&error_event(kind, "server_error", "idle"));
+            assert!(matches!(classify(&events[0]), EventClass::Error(_)));
+        }
+    }
+
+    #[test]
+    fn classifies_chat_chunks() {
+        let chunk = |data: &str| SseEvent {
+            event: None,
+            data: data.into(),
+        };
+
+        let role = chunk(r#"{"choices":[{"index":0,"delta":{"role":"assistant","content":""}}]}"#);
+        let text = chunk(r#"{"choices":[{"index":0,"delta":{"content":"hi"}}]}"#);
+        let error = chunk(r#"{"error":{"message":"slow down","type":"rate_limit_exceeded"}}"#);
+
+        assert!(matches!(classify(&role), EventClass::Other));
+        assert!(matches!(classify(&text), EventClass::Content));
+        let EventClass::Error(failure) = classify(&error) else {
+            panic!("expected error");
+        };
+        assert_eq!(failure.status.as_u16(), 429);
+    }
+
+    #[test]
+    fn classifies_responses_failure() {
+        let event = SseEvent {
+            event: Some("response.failed".into()),
+            data: r#"{"type":"response.failed","response":{"error":{"code":"rate_limit_exceeded","message":"slow down"}}}"#.into(),
+        };
+
+        let EventClass::Error(failure) = classify(&event) else {
+            panic!("expected error");
+        };
+        assert_eq!(failure.status.as_u16(), 429);
+    }
+}
@@ -0,0 +1,152 @@
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 10_000;
+// Off unless configured: long non-streamed generations and slow reasoning
+// starts are legitimate, and failing them over pays for the request twice
+const DEFAULT_FIRST_BYTE_TIMEOUT_MS: u64 = 0;
+const DEFAULT_STREAM_IDLE_TIMEOUT_MS: u64 = 120_000;
+
+/// Timeout settings; global in provider.json with per-provider overrides.
+/// A value of `0` disables that timeout.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimeoutConfig {
+    /// Maximum time to establish the TCP/TLS connection
+    #[serde(rename = "connectTimeoutMs", skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_ms: Option<u64>,
+    /// Maximum time until the response headers (and, for streams, the first content event);
+    /// off unless configured
+    #[serde(rename = "firstByteTimeoutMs", skip_serializing_if = "Option::is_none")]
+    pub first_byte_timeout_ms: Option<u64>,
+    /// Maximum gap between body chunks once the response is flowing
+    #[serde(
+        rename = "streamIdleTimeoutMs",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub stream_idle_timeout_ms: Option<u64>,
+}
+
+/// Effective timeouts for one provider; `None` means unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub connect: Option<Duration>,
+    pub first_byte: Option<Duration>,
+    pub stream_idle: Option<Duration>,
+}
+
+impl TimeoutConfig {
+    /// Resolve provider overrides against the global settings and defaults
+    pub fn resolve(&self, global: &TimeoutConfig) -> Timeouts {
+        let pick = |own: Option<u64>, global: Option<u64>, default: u64| {
+            let ms = own.or(global).unwrap_or(default);
+            (ms > 0).then(|| Duration::from_millis(ms))
+        };
+
+        Timeouts {
+            connect: pick(
+                self.connect_timeout_ms,
+                global.connect_timeout_ms,
+                DEFAULT_CONNECT_TIMEOUT_MS,
+            ),
+            first_byte: pick(
+                self.first_byte_timeout_ms,
+                global.first_byte_timeout_ms,
+                DEFAULT_FIRST_BYTE_TIMEOUT_MS,
+            ),
+            stream_idle: pick(
+                self.stream_idle_timeout_ms,
+                global.stream_idle_timeout_ms,
+                DEFAULT_STREAM_IDLE_TIMEOUT_MS,
+            ),
+        }
+    }
+}
+
+/// End a body stream when no chunk arrives within `idle`. For SSE the
+/// `on_timeout` event is emitted so the client sees a proper error;
+/// otherwise the body fails with an I/O error.
+pub fn with_idle_timeout<S, E>(
+    stream: S,
+    idle: Option<Duration>,
+    on_timeout: Option<Bytes>,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>> + Send
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    futures::stream::unfold(
+        (stream, on_timeout, false),
+        move |(mut stream, on_timeout, done)| async move {
+            if done {
+                return None;
+            }
+
+            let next = match idle {
+                Some(idle) => match tokio::time::timeout(idle, stream.next()).await {
+                    Ok(next) => next,
+                    Err(_) => {
+                        tracing::warn!("Upstream stream idle for {}ms, closing", idle.as_millis());
+                        let item = match on_timeout.clone() {
+                            Some(event) => Ok(event),
+                            None => Err(std::io::Error::new(
+                                std::io::ErrorKind::TimedOut,
+                                "upstream stream idle timeout",
+                            )),
+                        };
+                        return Some((item, (stream, on_timeout, true)));
+                    }
+                },
+                None => stream.next().await,
+            };
+
+            next.map(|item| {
+                (
+                    item.map_err(std::io::Error::other),
+                    (stream, on_timeout, false),
+                )
+            })
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provider_overrides_global_and_zero_disables() {
+        let global = TimeoutConfig {
+            connect_timeout_ms: Some(5_000),
+            first_byte_timeout_ms: Some(0),
+            stream_idle_timeout_ms: None,
+        };
+        let provider = TimeoutConfig {
+            connect_timeout_ms: Some(1_000),
+            ..Default::default()
+        };
+
+        let timeouts = provider.resolve(&global);
+        assert_eq!(timeouts.connect, Some(Duration::from_millis(1_000)));
+        assert_eq!(timeouts.first_byte, None);
+        assert_eq!(
+            timeouts.stream_idle,
+            Some(Duration::from_millis(DEFAULT_STREAM_IDLE_TIMEOUT_MS))
+        );
+    }
+
+    #[tokio::test]
+    async fn idle_timeout_emits_event_and_ends() {
+        let chunks = futures::stream::iter(vec![Ok::<_, std::io::Error>(Bytes::from("a"))])
+            .chain(futures::stream::pending());
+        let stream = with_idle_timeout(
+            Box::pin(chunks),
+            Some(Duration::from_millis(20)),
+            Some(Bytes::from("timeout")),
+        );
+
+        let items: Vec<Bytes> = stream.map(|item| item.unwrap()).collect().await;
+        assert_eq!(items, vec![Bytes::from("a"), Bytes::from("timeout")]);
+    }
+}
@@ -0,0 +1,423 @@
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderValue, Response, StatusCode},
+};
+use bytes::Bytes;
+use serde_json::{json, Value};
+use std::fmt;
+
+/// Statuses that describe a problem with the request itself. Every provider
+/// would reject it the same way, so the response goes straight back to the client.
+pub const DEFAULT_NON_RETRYABLE_STATUSES: &[u16] = &[400, 404, 413, 422];
+
+/// Diagnostic header listing the outcome of every provider attempt
+pub const ATTEMPTS_HEADER: &str = "x-cc-proxy-attempts";
+
+/// Per-provider classification of upstream error statuses
+#[derive(Debug, Clone, Default)]
+pub struct StatusPolicy {
+    /// Always fail over on these statuses
+    pub retryable: Vec<u16>,
+    /// Always return these statuses to the client
+    pub non_retryable: Vec<u16>,
+}
+
+impl StatusPolicy {
+    /// Whether an error status should fail over to the next provider.
+    /// Provider overrides win; otherwise everything except the
+    /// request-side errors in `DEFAULT_NON_RETRYABLE_STATUSES` fails over
+    /// (429, 5xx, 529, and key-specific 401/403).
+    pub fn is_retryable(&self, status: u16) -> bool {
+        if self.retryable.contains(&status) {
+            return true;
+        }
+        if self.non_retryable.contains(&status) {
+            return false;
+        }
+        !DEFAULT_NON_RETRYABLE_STATUSES.contains(&status)
+    }
+}
+
+/// A buffered upstream error response
+#[derive(Debug, Clone)]
+pub struct UpstreamFailure {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl UpstreamFailure {
+    /// Rank failures so the client sees the most actionable one:
+    /// 4xx (auth, quota, rate limit) beats 5xx
+    fn relevance(&self) -> u8 {
+        if self.status.is_client_error() {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+impl fmt::Display for UpstreamFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Provider returned error status: {} {}",
+            self.status,
+            body_preview(&self.body)
+        )
+    }
+}
+
+/// Why a single provider attempt did not produce a usable response
+pub enum AttemptError {
+    /// Transport failure (connect error, timeout, ...); try the next provider
+    Retryable(anyhow::Error),
+    /// Provider-side error status (429, 5xx, ...); try the next provider
+    RetryableStatus(UpstreamFailure),
+    /// Request-side failure; forward the upstream response unchanged
+    NonRetryable(Response<Body>),
+}
+
+impl From<anyhow::Error> for AttemptError {
+    fn from(err: anyhow::Error) -> Self {
+        AttemptError::Retryable(err)
+    }
+}
+
+/// Returned by `Router::route_request` when no provider produced a response
+#[derive(Debug, Default)]
+pub struct AllProvidersFailed {
+    pub model: String,
+    /// `(provider label, outcome)` for every candidate, in try order
+    pub attempts: Vec<(String, String)>,
+    /// Most relevant upstream error response seen
+    pub upstream: Option<UpstreamFailure>,
+}
+
+impl AllProvidersFailed {
+    pub fn record(&mut self, provider: String, outcome: impl Into<String>) {
+        self.attempts.push((provider, outcome.into()));
+    }
+
+    /// Record an upstream error response, keeping the most relevant one.
+    /// Later failures win ties.
+    pub fn record_upstream(&mut self, provider: String, failure: UpstreamFailure) {
+        self.record(provider, failure.status.as_u16().to_string());
+        let keep_existing = self
+            .upstream
+            .as_ref()
+            .is_some_and(|existing| existing.relevance() > failure.relevance());
+        if !keep_existing {
+            self.upstream = Some(failure);
+        }
+    }
+
+    fn attempts_header(&self) -> Option<HeaderValue> {
+        let value = self
+            .attempts
+            .iter()
+            .map(|(provider, outcome)| {
+                format!("{}={}", header_label(provider), header_label(outcome))
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        HeaderValue::from_str(&value).ok()
+    }
+}
+
+/// Percent-encode everything in a label that is not printable ASCII or
+/// would be ambiguous in the `name=outcome; ...` list
+fn header_label(label: &str) -> String {
+    let mut encoded = String::with_capacity(label.len());
+    for byte in label.bytes() {
+        match byte {
+            b'%' | b';' | b'=' => encoded.push_str(&format!("%{:02X}", byte)),
+            b' '..=b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+impl fmt::Display for AllProvidersFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "All {} providers failed for model: {}",
+            self.attempts.len(),
+            self.model
+        )?;
+        let skipped = self
+            .attempts
+            .iter()
+            .filter(|(_, outcome)| outcome == "circuit-open")
+            .count();
+        if skipped > 0 {
+            write!(f, " ({} skipped with open circuit)", skipped)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AllProvidersFailed {}
+
+/// Render a routing failure in the calling API's native error schema
+pub fn into_response(kind: &str, err: &anyhow::Error) -> Response<Body> {
+    let Some(failed) = err.downcast_ref::<AllProvidersFailed>() else {
+        return native_error_response(
+            kind,
+            StatusCode::BAD_GATEWAY,
+            &format!("All providers failed: {}", err),
+        );
+    };
+
+    let mut response = match &failed.upstream {
+        Some(upstream) => {
+            let body = native_error_body(kind, upstream.status, &upstream.body);
+            let mut response = json_response(upstream.status, body);
+            for (name, value) in &upstream.headers {
+                let skip = matches!(
+                    name.as_str(),
+                    "content-type" | "content-length" | "content-encoding"
+                );
+                if !skip {
+                    response.headers_mut().append(name.clone(), value.clone());
+                }
+            }
+            response
+        }
+        None => native_error_response(kind, StatusCode::BAD_GATEWAY, &failed.to_string()),
+    };
+
+    if let Some(value) = failed.attempts_header() {
+        response.headers_mut().insert(ATTEMPTS_HEADER, value);
+    }
+    response
+}
+
+/// Error response in the calling API's native schema
+/// (Anthropic for `claude`, OpenAI for everything else)
+pub fn native_error_response(kind: &str, status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, build_error(kind, status, None, message))
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Keep an upstream error body that already uses the native schema,
+/// otherwise extract its message and wrap it
+pub fn native_error_body(kind: &str, status: StatusCode, body: &[u8]) -> Value {
+    let parsed: Option<Value> = serde_json::from_slice(body).ok();
+
+    if let Some(value) = &parsed {
+        let is_native = if kind == "claude" {
+            value["type"] == "error" && value["error"].is_object()
+        } else {
+            value["error"]["message"].is_string()
+        };
+        if is_native {
+            return value.clone();
+        }
+    }
+
+    let upstream_type = parsed
+        .as_ref()
+        .and_then(|v| v["error"]["type"].as_str().map(str::to_string));
+    let message = parsed
+        .as_ref()
+        .and_then(extract_message)
+        .unwrap_or_else(|| {
+            let preview = body_preview(body);
+            if preview.is_empty() {
+                format!("Upstream returned {}", status)
+            } else {
+                preview
+            }
+        });
+
+    build_error(kind, status, upstream_type.as_deref(), &message)
+}
+
+fn extract_message(value: &Value) -> Option<String> {
+    [
+        &value["error"]["message"],
+        &value["error"],
+        &value["message"],
+        &value["detail"],
+    ]
+    .into_iter()
+    .find_map(|v| v.as_str().map(str::to_string))
+}
+
+fn build_error(kind: &str, status: StatusCode, error_type: Option<&str>, message: &str) -> Value {
+    let error_type = error_type.unwrap_or_else(|| default_error_type(kind, status));
+    if kind == "claude" {
+        json!({
+            "type": "error",
+            "error": { "type": error_type, "message": message }
+        })
+    } else {
+        json!({
+            "error": {
+                "message": message,
+                "type": error_type,
+                "param": null,
+                "code": null
+            }
+        })
+    }
+}
+
+fn default_error_type(kind: &str, status: StatusCode) -> &'static str {
+    let anthropic = kind == "claude";
+    match status.as_u16() {
+        400 | 413 | 422 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        429 if anthropic => "rate_limit_error",
+        429 => "rate_limit_exceeded",
+        529 if anthropic => "overloaded_error",
+        _ if anthropic => "api_error",
+        _ => "server_error",
+    }
+}
+
+/// Short, single-line preview of an upstream error body for logs
+pub fn body_preview(body: &[u8]) -> String {
+    let text = String::from_utf8_lossy(&body[..body.len().min(200)]);
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure(status: u16, body: &str) -> UpstreamFailure {
+        UpstreamFailure {
+            status: StatusCode::from_u16(status).unwrap(),
+            headers: HeaderMap::new(),
+            body: Bytes::from(body.to_string()),
+        }
+    }
+
+    async fn body_json(response: Response<Body>) -> Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[test]
+    fn default_policy_classifies_statuses() {
+        let policy = StatusPolicy::default();
+
+        for status in [429, 500, 502, 503, 529, 401, 403] {
+            assert!(policy.is_retryable(status), "{} should fail over", status);
+        }
+        for status in [400, 404, 413, 422] {
+            assert!(
+                !policy.is_retryable(status),
+                "{} should pass through",
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn provider_overrides_win() {
+        let policy = StatusPolicy {
+            retryable: vec![400],
+            non_retryable: vec![429],
+        };
+
+        assert!(policy.is_retryable(400));
+        assert!(!policy.is_retryable(429));
+        assert!(!policy.is_retryable(404));
+    }
+
+    #[test]
+    fn body_preview_collapses_whitespace() {
+        let body = b"{\n  \"error\": \"bad\"\n}";
+        assert_eq!(body_preview(body), "{ \"error\": \"bad\" }");
+    }
+
+    #[test]
+    fn keeps_most_relevant_upstream_failure() {
+        let mut failed = AllProvidersFailed::default();
+        failed.record_upstream("a".into(), failure(429, "limited"));
+        failed.record_upstream("b".into(), failure(503, "down"));
+        failed.record("c".into(), "error");
+
+        assert_eq!(
+            failed.upstream.unwrap().status,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(failed.attempts.len(), 3);
+    }
+
+    #[test]
+    fn attempts_header_encodes_non_ascii_labels() {
+        let mut failed = AllProvidersFailed::default();
+        failed.record("中转".into(), "error");
+        failed.record("relay;b=2".into(), "503");
+
+        assert_eq!(
+            failed.attempts_header().unwrap(),
+            "%E4%B8%AD%E8%BD%AC=error; relay%3Bb%3D2=503"
+        );
+    }
+
+    #[tokio::test]
+    async fn forwards_native_anthropic_error_body() {
+        let mut failed = AllProvidersFailed::default();
+        let body = r#"{"type":"error","error":{"type":"invalid_request_error","message":"Your credit balance is too low"}}"#;
+        failed.record_upstream("relay".into(), failure(402, body));
+
+        let response = into_response("claude", &anyhow::Error::new(failed));
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(response.headers()[ATTEMPTS_HEADER], "relay=402");
+        assert_eq!(
+            body_json(response).await["error"]["message"],
+            "Your credit balance is too low"
+        );
+    }
+
+    #[tokio::test]
+    async fn wraps_foreign_error_in_openai_schema() {
+        let mut failed = AllProvidersFailed::default();
+        failed.record_upstream(
+            "relay".into(),
+            failure(503, r#"{"message":"upstream busy"}"#),
+        );
+
+        let response = into_response("codex", &anyhow::Error::new(failed));
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let body = body_json(response).await;
+        assert_eq!(body["error"]["message"], "upstream busy");
+        assert_eq!(body["error"]["type"], "server_error");
+    }
+
+    #[tokio::test]
+    async fn transport_failures_become_bad_gateway() {
+        let mut failed = AllProvidersFailed {
+            model: "claude-sonnet-4-5".into(),
+            ..Default::default()
+        };
+        failed.record("relay".into(), "error");
+
+        let response = into_response("claude", &anyhow::Error::new(failed));
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+
+        let body = body_json(response).await;
+        assert_eq!(body["type"], "error");
+        assert_eq!(body["error"]["type"], "api_error");
+    }
+}
@@ -0,0 +1,684 @@
+use crate::pricing::Cost;
+use crate::sse::SseParser;
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Lifetime of Anthropic's extended (`"ttl": "1h"`) prompt cache
+const ONE_HOUR_CACHE_SECS: u64 = 3600;
+
+/// Largest non-streamed body kept aside for usage parsing
+const MAX_OBSERVED_BODY_BYTES: usize = 8 * 1024 * 1024;
+
+/// Requests whose individual cost is kept for `GET /usage`
+const RECENT_REQUESTS: usize = 50;
+
+/// Token usage of one response, normalized across APIs.
+/// `input_tokens` excludes cache reads and writes (Anthropic semantics).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    #[serde(rename = "inputTokens")]
+    pub input_tokens: u64,
+    #[serde(rename = "outputTokens")]
+    pub output_tokens: u64,
+    #[serde(rename = "cacheReadTokens")]
+    pub cache_read_tokens: u64,
+    #[serde(rename = "cacheWriteTokens")]
+    pub cache_write_tokens: u64,
+    /// Part of `cache_write_tokens` written with the 1h TTL
+    #[serde(rename = "cacheWrite1hTokens")]
+    pub cache_write_1h_tokens: u64,
+}
+
+impl Usage {
+    /// Apply a `usage` object. Only fields present are overwritten, so a
+    /// `message_delta` updates the counts from `message_start`.
+    pub fn update(&mut self, usage: &Value) {
+        let field = |pointer: &str| usage.pointer(pointer).and_then(Value::as_u64);
+
+        // OpenAI counts cached tokens inside the prompt total
+        let openai_cached = field("/input_tokens_details/cached_tokens")
+            .or_else(|| field("/prompt_tokens_details/cached_tokens"));
+        if let Some(cached) = openai_cached {
+            self.cache_read_tokens = cached;
+        }
+        // Responses translated from Anthropic count cache writes in that total too
+        let openai_written = openai_cached.and(field("/cache_creation_input_tokens"));
+        if let Some(input) = field("/input_tokens").or_else(|| field("/prompt_tokens")) {
+            self.input_tokens = input
+                .saturating_sub(openai_cached.unwrap_or(0))
+                .saturating_sub(openai_written.unwrap_or(0));
+        }
+        if let Some(output) = field("/output_tokens").or_else(|| field("/completion_tokens")) {
+            self.output_tokens = output;
+        }
+        if let Some(read) = field("/cache_read_input_tokens") {
+            self.cache_read_tokens = read;
+        }
+        if let Some(write) = field("/cache_creation_input_tokens") {
+            self.cache_write_tokens = write;
+        }
+        if let Some(write_1h) = field("/cache_creation/ephemeral_1h_input_tokens") {
+            self.cache_write_1h_tokens = write_1h;
+        }
+    }
+
+    pub fn add(&mut self, other: &Usage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_read_tokens += other.cache_read_tokens;
+        self.cache_write_tokens += other.cache_write_tokens;
+        self.cache_write_1h_tokens += other.cache_write_1h_tokens;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Usage::default()
+    }
+
+    /// Upstream cache lifetime implied by the cache writes, when longer than the default
+    pub fn cache_ttl_secs(&self) -> Option<u64> {
+        (self.cache_write_1h_tokens > 0).then_some(ONE_HOUR_CACHE_SECS)
+    }
+}
+
+/// The `usage` object of a response body or stream event, wherever the API puts it
+fn find_usage(value: &Value) -> Option<&Value> {
+    // Anthropic message_start: message.usage; Responses: response.usage
+    [
+        &value["usage"],
+        &value["message"]["usage"],
+        &value["response"]["usage"],
+    ]
+    .into_iter()
+    .find(|v| v.is_object())
+}
+
+/// Collects usage from response chunks as they pass through
+struct UsageObserver<F: FnOnce(Usage)> {
+    sse: Option<SseParser>,
+    body: Vec<u8>,
+    usage: Usage,
+    on_complete: Option<F>,
+}
+
+impl<F: FnOnce(Usage)> UsageObserver<F> {
+    fn feed(&mut self, chunk: &[u8]) {
+        match &mut self.sse {
+            Some(parser) => {
+                for event in parser.feed(chunk) {
+                    // Skip the JSON parse for the bulk of delta events
+                    if !event.data.contains("\"usage\"") {
+                        continue;
+                    }
+                    if let Some(value) = event.json() {
+                        if let Some(usage) = find_usage(&value) {
+                            self.usage.update(usage);
+                        }
+                    }
+                }
+            }
+            None => {
+                if self.body.len() + chunk.len() <= MAX_OBSERVED_BODY_BYTES {
+                    self.body.extend_from_slice(chunk);
+                }
+            }
+        }
+    }
+}
+
+impl<F: FnOnce(Usage)> Drop for UsageObserver<F> {
+    // Runs when the body finishes or the client disconnects
+    fn drop(&mut self) {
+        if self.sse.is_none() {
+            if let Ok(value) = serde_json::from_slice::<Value>(&self.body) {
+                if let Some(usage) = find_usage(&value) {
+                    self.usage.update(usage);
+                }
+            }
+        }
+        if let Some(on_complete) = self.on_complete.take() {
+            if !self.usage.is_empty() {
+                on_complete(self.usage);
+            }
+        }
+    }
+}
+
+/// Pass a response body through unchanged while collecting its token usage.
+/// `on_complete` is called once the body ends (or is dropped) if usage was found.
+pub fn observe_usage<S, E, F>(
+    stream: S,
+    is_event_stream: bool,
+    on_complete: F,
+) -> impl Stream<Item = Result<Bytes, E>> + Send
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    F: FnOnce(Usage) + Send + 'static,
+{
+    let mut observer = UsageObserver {
+        sse: is_event_stream.then(SseParser::new),
+        body: Vec::new(),
+        usage: Usage::default(),
+        on_complete: Some(on_complete),
+    };
+    stream.map(move |chunk| {
+        if let Ok(bytes) = &chunk {
+            observer.feed(bytes);
+        }
+        chunk
+    })
+}
+
+/// Accumulated usage for one day / provider / model / client combination
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageRecord {
+    /// UTC date, `YYYY-MM-DD`
+    #[serde(default)]
+    pub day: String,
+    pub provider: String,
+    pub model: String,
+    pub client: String,
+    pub requests: u64,
+    #[serde(flatten)]
+    pub usage: Usage,
+    /// Estimated spend from the `pricing` table
+    #[serde(rename = "costUsd", default)]
+    pub cost_usd: f64,
+    /// Spend avoided by prompt cache reads
+    #[serde(rename = "cacheSavingsUsd", default)]
+    pub cache_savings_usd: f64,
+}
+
+/// Usage and estimated spend of one recent request
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RequestCost {
+    /// Unix time the usage was reported
+    pub at: u64,
+    pub provider: String,
+    pub model: String,
+    pub client: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+    /// Unset when no `pricing` rule matches the model
+    #[serde(rename = "costUsd", skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
+    #[serde(rename = "cacheSavingsUsd", skip_serializing_if = "Option::is_none")]
+    pub cache_savings_usd: Option<f64>,
+}
+
+/// `(day, provider, model, client)`
+type UsageKey = (String, String, String, String);
+
+/// Running token totals per day, provider, model and client, plus the
+/// cost of the most recent requests
+#[derive(Default)]
+pub struct UsageStats {
+    entries: Mutex<HashMap<UsageKey, UsageRecord>>,
+    recent: Mutex<VecDeque<RequestCost>>,
+}
+
+impl UsageStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from previously saved totals
+    pub fn from_records(records: Vec<UsageRecord>) -> Self {
+        let stats = Self::new();
+        {
+            let mut entries = stats.entries.lock().unwrap();
+            for record in records {
+                let key = (
+                    record.day.clone(),
+                    record.provider.clone(),
+                    record.model.clone(),
+                    record.client.clone(),
+                );
+                match entries.get_mut(&key) {
+                    Some(entry) => entry.merge(&record),
+                    None => {
+                        entries.insert(key, record);
+                    }
+                }
+            }
+        }
+        stats
+    }
+
+    pub fn record(
+        &self,
+        provider: &str,
+        model: &str,
+        client: &str,
+        usage: &Usage,
+        cost: Option<Cost>,
+    ) {
+        let day = today();
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .entry((
+                day.clone(),
+                provider.to_string(),
+                model.to_string(),
+                client.to_string(),
+            ))
+            .or_insert_with(|| UsageRecord {
+                day,
+                provider: provider.to_string(),
+                model: model.to_string(),
+                client: client.to_string(),
+                ..Default::default()
+            });
+
+        let estimate = cost.unwrap_or_default();
+        entry.merge(&UsageRecord {
+            requests: 1,
+            usage: *usage,
+            cost_usd: estimate.total,
+            cache_savings_usd: estimate.cache_savings,
+            ..Default::default()
+        });
+        drop(entries);
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_REQUESTS {
+            recent.pop_back();
+        }
+        recent.push_front(RequestCost {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            client: client.to_string(),
+            usage: *usage,
+            cost_usd: cost.map(|c| c.total),
+            cache_savings_usd: cost.map(|c| c.cache_savings),
+        });
+    }
+
+    /// Cost of the latest requests, newest first
+    pub fn recent(&self) -> Vec<RequestCost> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Today's totals for one client, across providers and models
+    pub fn client_today(&self, client: &str) -> UsageRecord {
+        let day = today();
+        let mut totals = UsageRecord::default();
+        for (key, record) in self.entries.lock().unwrap().iter() {
+            if key.0 == day && key.3 == client {
+                totals.merge(record);
+            }
+        }
+        totals
+    }
+
+    pub fn snapshot(&self) -> Vec<UsageRecord> {
+        let entries = self.entries.lock().unwrap();
+        let mut records: Vec<UsageRecord> = entries.values().cloned().collect();
+        records.sort_by(|a, b| {
+            (&a.day, &a.provider, &a.model, &a.client).cmp(&(
+                &b.day,
+                &b.provider,
+                &b.model,
+                &b.client,
+            ))
+        });
+        records
+    }
+}
+
+impl UsageRecord {
+    fn merge(&mut self, other: &UsageRecord) {
+        self.requests += other.requests;
+        self.usage.add(&other.usage);
+        self.cost_usd += other.cost_usd;
+        self.cache_savings_usd += other.cache_savings_usd;
+    }
+}
+
+/// Spend rolled up for `cc-proxy status` and `GET /usage`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageSummary {
+    #[serde(rename = "totalCostUsd")]
+    pub total_cost_usd: f64,
+    #[serde(rename = "cacheSavingsUsd")]
+    pub cache_savings_usd: f64,
+    #[serde(rename = "byDay")]
+    pub by_day: BTreeMap<String, UsageRecord>,
+    #[serde(rename = "byProvider")]
+    pub by_provider: BTreeMap<String, UsageRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub records: Vec<UsageRecord>,
+    /// Latest requests with their individual estimated cost, newest first
+    #[serde(
+        rename = "recentRequests",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub recent_requests: Vec<RequestCost>,
+}
+
+impl UsageSummary {
+    pub fn new(records: Vec<UsageRecord>) -> Self {
+        let mut summary = Self::default();
+        for record in &records {
+            summary.total_cost_usd += record.cost_usd;
+            summary.cache_savings_usd += record.cache_savings_usd;
+            summary
+                .by_day
+                .entry(record.day.clone())
+                .or_insert_with(|| UsageRecord {
+                    day: record.day.clone(),
+                    ..Default::default()
+                })
+                .merge(record);
+            summary
+                .by_provider
+                .entry(record.provider.clone())
+                .or_insert_with(|| UsageRecord {
+                    provider: record.provider.clone(),
+                    ..Default::default()
+                })
+                .merge(record);
+        }
+        summary.records = records;
+        summary
+    }
+}
+
+/// Current UTC date as `YYYY-MM-DD`
+pub fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    civil_date((secs / 86_400) as i64)
+}
+
+/// Days since the Unix epoch to a proleptic Gregorian date
+fn civil_date(days: i64) -> String {
+    // Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Location of the usage totals read by `cc-proxy status`
+pub fn usage_file_path() -> Result<PathBuf> {
+    let home = std::env::var("HOME").context("HOME environment variable not set")?;
+    Ok(PathBuf::from(home).join(".cc-proxy").join("usage.json"))
+}
+
+/// Save usage totals; the file is replaced atomically so a crash mid-write
+/// never leaves it truncated
+pub fn write_usage_file(records: &[UsageRecord]) -> Result<()> {
+    save_usage(&usage_file_path()?, records)
+}
+
+fn save_usage(path: &Path, records: &[UsageRecord]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(records)?)
+        .with_context(|| format!("Failed to write usage totals: {:?}", tmp))?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to write usage totals: {:?}", path))
+}
+
+/// Previously saved usage totals. A file that cannot be read is moved aside
+/// rather than left for the next save to overwrite.
+pub fn read_usage_file() -> Vec<UsageRecord> {
+    match usage_file_path() {
+        Ok(path) => load_usage(&path),
+        Err(e) => {
+            tracing::warn!("Not loading usage totals: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn load_usage(path: &Path) -> Vec<UsageRecord> {
+    if !path.exists() {
+        return Vec::new();
+    }
+    let loaded = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read usage totals: {:?}", path))
+        .and_then(|content| {
+            serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse usage totals: {:?}", path))
+        });
+
+    match loaded {
+        Ok(records) => records,
+        Err(e) => {
+            let secs = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            let aside = path.with_extension(format!("json.corrupt-{}", secs));
+            match fs::rename(path, &aside) {
+                Ok(()) => tracing::warn!("{:#}; moved it to {:?}", e, aside),
+                Err(rename) => tracing::error!(
+                    "{:#}; moving it aside failed too ({}), it will be overwritten",
+                    e,
+                    rename
+                ),
+            }
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn parses_anthropic_usage_with_cache_breakdown() {
+        let mut usage = Usage::default();
+        usage.update(&json!({
+            "input_tokens": 12,
+            "cache_creation_input_tokens": 2000,
+            "cache_read_input_tokens": 500,
+            "cache_creation": { "ephemeral_5m_input_tokens": 0, "ephemeral_1h_input_tokens": 2000 },
+            "output_tokens": 1
+        }));
+        usage.update(&json!({ "output_tokens": 42 }));
+
+        assert_eq!(usage.input_tokens, 12);
+        assert_eq!(usage.output_tokens, 42);
+        assert_eq!(usage.cache_read_tokens, 500);
+        assert_eq!(usage.cache_write_tokens, 2000);
+        assert_eq!(usage.cache_ttl_secs(), Some(ONE_HOUR_CACHE_SECS));
+    }
+
+    #[test]
+    fn openai_input_excludes_cached_tokens() {
+        let mut usage = Usage::default();
+        usage.update(&json!({
+            "input_tokens": 1000,
+            "input_tokens_details": { "cached_tokens": 800 },
+            "output_tokens": 50
+        }));
+
+        assert_eq!(usage.input_tokens, 200);
+        assert_eq!(usage.cache_read_tokens, 800);
+        assert_eq!(usage.cache_ttl_secs(), None);
+    }
+
+    #[tokio::test]
+    async fn observes_sse_usage_without_changing_the_stream() {
+        let chunks = vec![
+            Bytes::from(
+                "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":10,\"output_tokens\":1}}}\n\n",
+            ),
+            Bytes::from(
+                "event: message_delta\ndata: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":7}}\n\n",
+            ),
+        ];
+        let seen = Arc::new(Mutex::new(None));
+        let sink = seen.clone();
+
+        let out: Vec<Bytes> = observe_usage(
+            futures::stream::iter(chunks.clone()).map(Ok::<_, std::io::Error>),
+            true,
+            move |u| *sink.lock().unwrap() = Some(u),
+        )
+        .map(|c| c.unwrap())
+        .collect()
+        .await;
+
+        assert_eq!(out, chunks);
+        let usage = seen.lock().unwrap().unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (10, 7));
+    }
+
+    #[test]
+    fn stats_accumulate_per_provider_model_and_client() {
+        let stats = UsageStats::new();
+        let usage = Usage {
+            input_tokens: 10,
+            output_tokens: 5,
+            ..Default::default()
+        };
+        let cost = Cost {
+            total: 0.25,
+            cache_savings: 0.0,
+        };
+        stats.record("relay", "claude-sonnet-4-5", "alice", &usage, Some(cost));
+        stats.record("relay", "claude-sonnet-4-5", "alice", &usage, Some(cost));
+        stats.record("relay", "claude-sonnet-4-5", "bob", &usage, None);
+
+        let records = stats.snapshot();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].client, "alice");
+        assert_eq!(records[0].day, today());
+        assert_eq!(records[0].requests, 2);
+        assert_eq!(records[0].usage.output_tokens, 10);
+        assert_eq!(records[0].cost_usd, 0.5);
+
+        let restored = UsageStats::from_records(records.clone());
+        assert_eq!(restored.snapshot(), records);
+    }
+
+    #[test]
+    fn keeps_cost_of_recent_requests() {
+        let stats = UsageStats::new();
+        let usage = Usage {
+            input_tokens: 10,
+            ..Default::default()
+        };
+        for n in 0..RECENT_REQUESTS + 5 {
+            let cost = Cost {
+                total: n as f64,
+                cache_savings: 0.0,
+            };
+            stats.record("relay", "claude-sonnet-4-5", "alice", &usage, Some(cost));
+        }
+        stats.record("relay", "unpriced", "alice", &usage, None);
+
+        let recent = stats.recent();
+        assert_eq!(recent.len(), RECENT_REQUESTS);
+        assert_eq!(recent[0].model, "unpriced");
+        assert_eq!(recent[0].cost_usd, None);
+        assert_eq!(recent[1].cost_usd, Some((RECENT_REQUESTS + 4) as f64));
+    }
+
+    #[test]
+    fn summary_rolls_up_by_day_and_provider() {
+        let record = |day: &str, provider: &str, cost_usd: f64| UsageRecord {
+            day: day.into(),
+            provider: provider.into(),
+            requests: 1,
+            cost_usd,
+            ..Default::default()
+        };
+        let summary = UsageSummary::new(vec![
+            record("2026-01-01", "a", 1.0),
+            record("2026-01-01", "b", 2.0),
+            record("2026-01-02", "a", 4.0),
+        ]);
+
+        assert_eq!(summary.total_cost_usd, 7.0);
+        assert_eq!(summary.by_day["2026-01-01"].cost_usd, 3.0);
+        assert_eq!(summary.by_provider["a"].cost_usd, 5.0);
+        assert_eq!(summary.by_provider["a"].requests, 2);
+    }
+
+    #[test]
+    fn civil_date_from_epoch_days() {
+        assert_eq!(civil_date(0), "1970-01-01");
+        assert_eq!(civil_date(19_782), "2024-02-29");
+        assert_eq!(civil_date(20_742), "2026-10-16");
+    }
+
+    #[test]
+    fn corrupt_usage_file_is_moved_aside_not_overwritten() {
+        let dir = std::env::temp_dir().join(format!(
+            "cc-proxy-usage-{}-{}",
+            std::process::id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("usage.json");
+        fs::write(&path, r#"[{"day":"2026-01-01","provid"#).unwrap();
+        let saved = vec![UsageRecord {
+            day: "2026-01-02".into(),
+            requests: 1,
+            ..Default::default()
+        }];
+
+        assert!(load_usage(&path).is_empty());
+        save_usage(&path, &saved).unwrap();
+
+        let aside: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|p| p.to_string_lossy().contains(".corrupt-"))
+            .collect();
+        assert_eq!(aside.len(), 1);
+        assert_eq!(
+            fs::read_to_string(&aside[0]).unwrap(),
+            r#"[{"day":"2026-01-01","provid"#
+        );
+        assert_eq!(load_usage(&path), saved);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn observes_json_body_usage() {
+        let body = r#"{"id":"resp_1","usage":{"input_tokens":5,"output_tokens":3}}"#;
+        let seen = Arc::new(Mutex::new(None));
+        let sink = seen.clone();
+
+        let stream = futures::stream::iter(vec![Ok::<_, std::io::Error>(Bytes::from(body))]);
+        let _: Vec<_> = observe_usage(stream, false, move |u| *sink.lock().unwrap() = Some(u))
+            .collect()
+            .await;
+
+        assert_eq!(seen.lock().unwrap().unwrap().output_tokens, 3);
+    }
+}